@@ -0,0 +1,172 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::Builder;
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Replaces the contents of `path` without ever leaving it half-written.
+///
+/// The data goes to a temporary file in the same directory, which is synced
+/// and then renamed over `path`. The directory is synced afterwards so the
+/// rename itself survives a crash. The directory is created if missing.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = parent_dir(path);
+    fs::create_dir_all(dir)?;
+
+    let mut temp_file = Builder::new()
+        .prefix(&temp_prefix(path))
+        .suffix(TEMP_SUFFIX)
+        .tempfile_in(dir)?;
+
+    temp_file.write_all(contents)?;
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(path).map_err(|e| e.error)?;
+
+    sync_dir(dir)
+}
+
+/// Temporary files left behind by a `write` that never reached the rename,
+/// newest first.
+pub fn leftovers(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = parent_dir(path);
+    let prefix = temp_prefix(path);
+
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if name.starts_with(&prefix) && name.ends_with(TEMP_SUFFIX) {
+            let modified = entry.metadata()?.modified()?;
+            found.push((modified, entry.path()));
+        }
+    }
+
+    found.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Cleans up after an interrupted `write`.
+///
+/// If `path` holds valid data the leftovers are stale and get removed.
+/// Otherwise the newest leftover that `is_valid` accepts is moved into place.
+/// Returns the leftover that was restored, if any.
+pub fn recover<F>(path: &Path, is_valid: F) -> io::Result<Option<PathBuf>>
+where
+    F: Fn(&str) -> bool,
+{
+    let leftovers = leftovers(path)?;
+
+    if leftovers.is_empty() {
+        return Ok(None);
+    }
+
+    let current_ok = fs::read_to_string(path)
+        .map(|data| data.trim().is_empty() || is_valid(&data))
+        .unwrap_or(false);
+
+    let mut restored = None;
+
+    for leftover in leftovers {
+        if !current_ok && restored.is_none() {
+            let data = fs::read_to_string(&leftover).unwrap_or_default();
+            if !data.trim().is_empty() && is_valid(&data) {
+                fs::rename(&leftover, path)?;
+                restored = Some(leftover);
+                continue;
+            }
+        }
+        fs::remove_file(&leftover)?;
+    }
+
+    if restored.is_some() {
+        sync_dir(parent_dir(path))?;
+    }
+
+    Ok(restored)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn temp_prefix(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!(".{name}.")
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    // Directories cannot be opened for syncing on this platform; the rename
+    // is as durable as the filesystem makes it.
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn leftover_in(dir: &Path, contents: &str) -> PathBuf {
+        let path = dir.join(format!(".marc.json.abc123{TEMP_SUFFIX}"));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn write_replaces_contents_and_leaves_no_temp_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("marc.json");
+
+        write(&path, b"first").unwrap();
+        write(&path, b"second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert!(leftovers(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recover_removes_stale_leftover_when_file_is_valid() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("marc.json");
+        fs::write(&path, "good").unwrap();
+        let leftover = leftover_in(dir.path(), "goo");
+
+        let restored = recover(&path, |data| data == "good").unwrap();
+
+        assert!(restored.is_none());
+        assert!(!leftover.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good");
+    }
+
+    #[test]
+    fn recover_restores_complete_leftover_over_truncated_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("marc.json");
+        fs::write(&path, "go").unwrap();
+        leftover_in(dir.path(), "good");
+
+        let restored = recover(&path, |data| data == "good").unwrap();
+
+        assert!(restored.is_some());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good");
+        assert!(leftovers(&path).unwrap().is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::todo;
 use std::io::{self, BufRead, BufReader, IsTerminal};
 use std::str::FromStr;
 
@@ -29,7 +30,7 @@ macro_rules! define_args {
         #[derive(Debug, Clone)]
         struct ArgSpec {
             name: &'static str,
-            short: char,
+            short: Option<char>,
             long: &'static str,
             kind: ArgKind,
         }
@@ -41,13 +42,13 @@ macro_rules! define_args {
                         $(
                             ArgSpec {
                                 name: stringify!($arg_name),
-                                short: $short,
+                                short: Some($short),
                                 long: $long,
                                 kind: ArgKind::$kind,
                             },
                             ArgSpec {
                                 name: "help",
-                                short: 'h',
+                                short: Some('h'),
                                 long: "--help",
                                 kind: ArgKind::Flag
                             }
@@ -67,6 +68,106 @@ define_args! {
             long: "tag",
             kind: Option,
         },
+        due: {
+            short: 'd',
+            long: "due",
+            kind: Option,
+        },
+        priority: {
+            short: 'p',
+            long: "priority",
+            kind: Option,
+        },
+        parent: {
+            short: 'P',
+            long: "parent",
+            kind: Option,
+        },
+        depends_on: {
+            short: 'D',
+            long: "depends-on",
+            kind: Option,
+        },
+        recur: {
+            short: 'r',
+            long: "recur",
+            kind: Option,
+        },
+    },
+    Block: {},
+    Unblock: {},
+    Graph: {},
+    Recur: {
+        stop: {
+            short: 's',
+            long: "stop",
+            kind: Flag,
+        },
+    },
+    Modify: {
+        tag: {
+            short: 't',
+            long: "tag",
+            kind: Option,
+        },
+        due: {
+            short: 'd',
+            long: "due",
+            kind: Option,
+        },
+        priority: {
+            short: 'p',
+            long: "priority",
+            kind: Option,
+        },
+        with_tag: {
+            short: 'w',
+            long: "with-tag",
+            kind: Option,
+        },
+        with_status: {
+            short: 's',
+            long: "with-status",
+            kind: Option,
+        },
+        yes: {
+            short: 'y',
+            long: "yes",
+            kind: Flag,
+        },
+    },
+    Next: {},
+    Tag: {
+        create: {
+            short: 'c',
+            long: "create",
+            kind: Flag,
+        },
+        prune: {
+            short: 'p',
+            long: "prune",
+            kind: Flag,
+        },
+        color: {
+            short: 'C',
+            long: "color",
+            kind: Option,
+        },
+        description: {
+            short: 'd',
+            long: "description",
+            kind: Option,
+        },
+        to: {
+            short: 't',
+            long: "to",
+            kind: Option,
+        },
+        strip: {
+            short: 's',
+            long: "strip",
+            kind: Flag,
+        },
     },
     Log: {
         tag: {
@@ -83,6 +184,36 @@ define_args! {
              short: 'u',
              long: "undone",
              kind: Flag,
+         },
+         overdue: {
+             short: 'o',
+             long: "overdue",
+             kind: Flag,
+         },
+         due_before: {
+             short: 'b',
+             long: "due-before",
+             kind: Option,
+         },
+         any: {
+             short: 'a',
+             long: "any",
+             kind: Flag,
+         },
+         blocked: {
+             short: 'B',
+             long: "blocked",
+             kind: Flag,
+         },
+         ready: {
+             short: 'r',
+             long: "ready",
+             kind: Flag,
+         },
+         status: {
+             short: 's',
+             long: "status",
+             kind: Option,
          }
     },
     Remove: {
@@ -92,12 +223,163 @@ define_args! {
             kind: Flag,
         },
     },
-    Edit: {},
+    Migrate: {
+        to: {
+            short: 't',
+            long: "to",
+            kind: Option,
+        },
+        force: {
+            short: 'f',
+            long: "force",
+            kind: Flag,
+        },
+    },
+    Move: {
+        to: {
+            short: 't',
+            long: "to",
+            kind: Option,
+        },
+    },
+    List: {
+        force: {
+            short: 'f',
+            long: "force",
+            kind: Flag,
+        },
+    },
+    Lists: {},
+    Undo: {
+        preview: {
+            short: 'l',
+            long: "list",
+            kind: Flag,
+        },
+    },
+    Redo: {},
+    History: {
+        check: {
+            short: 'c',
+            long: "check",
+            kind: Flag,
+        },
+    },
+    Init: {
+        single: {
+            short: 's',
+            long: "single",
+            kind: Flag,
+        },
+    },
+    Edit: {
+        tag: {
+             short: 't',
+             long: "tag",
+             kind: Option,
+         },
+         done: {
+             short: 'd',
+             long: "done",
+             kind: Flag,
+         },
+         undone: {
+             short: 'u',
+             long: "undone",
+             kind: Flag,
+         },
+         overdue: {
+             short: 'o',
+             long: "overdue",
+             kind: Flag,
+         },
+         due_before: {
+             short: 'b',
+             long: "due-before",
+             kind: Option,
+         },
+         any: {
+             short: 'a',
+             long: "any",
+             kind: Flag,
+         },
+         blocked: {
+             short: 'B',
+             long: "blocked",
+             kind: Flag,
+         },
+         ready: {
+             short: 'r',
+             long: "ready",
+             kind: Flag,
+         },
+         status: {
+             short: 's',
+             long: "status",
+             kind: Option,
+         }
+    },
+    Config: {},
     Help: {},
     Done: {},
+    Start: {},
+    Wait: {},
+    Cancel: {},
+    Reopen: {
+        tag: {
+            short: 't',
+            long: "tag",
+            kind: Option,
+        },
+        any: {
+            short: 'a',
+            long: "any",
+            kind: Flag,
+        },
+        status: {
+            short: 's',
+            long: "status",
+            kind: Option,
+        },
+        yes: {
+            short: 'y',
+            long: "yes",
+            kind: Flag,
+        },
+    },
+    Status: {},
     Version: {}
 }
 
+/// Arguments accepted by every subcommand, before or after it. They have no
+/// short form so they never clash with a subcommand's own switches.
+const GLOBAL_ARGS: &[ArgSpec] = &[
+    ArgSpec {
+        name: "file",
+        short: None,
+        long: "file",
+        kind: ArgKind::Option,
+    },
+    ArgSpec {
+        name: "config",
+        short: None,
+        long: "config",
+        kind: ArgKind::Option,
+    },
+    ArgSpec {
+        name: "list",
+        short: None,
+        long: "list",
+        kind: ArgKind::Option,
+    },
+    ArgSpec {
+        name: "global",
+        short: None,
+        long: "global",
+        kind: ArgKind::Flag,
+    },
+];
+
 impl FromStr for Subcommand {
     type Err = String;
 
@@ -108,6 +390,27 @@ impl FromStr for Subcommand {
             "log" => Ok(Subcommand::Log),
             "edit" => Ok(Subcommand::Edit),
             "done" => Ok(Subcommand::Done),
+            "start" => Ok(Subcommand::Start),
+            "wait" => Ok(Subcommand::Wait),
+            "cancel" => Ok(Subcommand::Cancel),
+            "reopen" | "undone" => Ok(Subcommand::Reopen),
+            "status" => Ok(Subcommand::Status),
+            "migrate" => Ok(Subcommand::Migrate),
+            "config" => Ok(Subcommand::Config),
+            "move" | "mv" => Ok(Subcommand::Move),
+            "list" => Ok(Subcommand::List),
+            "lists" => Ok(Subcommand::Lists),
+            "init" => Ok(Subcommand::Init),
+            "undo" => Ok(Subcommand::Undo),
+            "redo" => Ok(Subcommand::Redo),
+            "history" => Ok(Subcommand::History),
+            "next" => Ok(Subcommand::Next),
+            "modify" | "mod" => Ok(Subcommand::Modify),
+            "tag" | "tags" => Ok(Subcommand::Tag),
+            "block" => Ok(Subcommand::Block),
+            "unblock" => Ok(Subcommand::Unblock),
+            "graph" => Ok(Subcommand::Graph),
+            "recur" => Ok(Subcommand::Recur),
             "--help" | "help" | "-h" => Ok(Subcommand::Help),
             "--version" | "v" => Ok(Subcommand::Version),
             _ => Err(format!("unknown subcommand \"{s}\"")),
@@ -130,6 +433,14 @@ impl Arg {
             _ => None,
         })
     }
+    pub fn get_options(args: &[Arg], option_name: &String) -> Vec<String> {
+        args.iter()
+            .filter_map(|entry| match entry {
+                Arg::Option { name, value } if name == option_name => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
     pub fn get_flag(args: &[Arg], flag_name: &String) -> bool {
         args.iter()
             .any(|entry| matches!(entry, Arg::Flag(str) if str == flag_name))
@@ -154,7 +465,21 @@ impl CommandLine {
             return Err("Invalid use".into()); // TODO: create an error enum and
         }
 
-        let subcommand = match tokens.get(1) {
+        // global args given before the subcommand
+        let mut position = 1;
+        while let Some(token) = tokens.get(position) {
+            let global = token
+                .strip_prefix("--")
+                .and_then(|name| GLOBAL_ARGS.iter().find(|spec| spec.long == name));
+
+            match global {
+                Some(spec) if spec.kind == ArgKind::Flag => position += 1,
+                Some(_) => position += 2,
+                None => break,
+            }
+        }
+
+        let subcommand = match tokens.get(position) {
             Some(token) => match Subcommand::from_str(token) {
                 Ok(cmd) => cmd,
                 Err(_) => return Err(format!("unknown subcommand \"{token}\"").into()),
@@ -162,8 +487,8 @@ impl CommandLine {
             None => return Err("command not found".into()),
         };
 
-        // args without program name and subcommand
-        let mut rem_args = tokens[2..].to_vec();
+        // args after the subcommand
+        let mut rem_args = tokens[position + 1..].to_vec();
 
         if let Some(stdin_args) = read_stdin() {
             println!("{:?}", stdin_args);
@@ -172,7 +497,16 @@ impl CommandLine {
 
         let arg_spec = get_arg_specs_for(subcommand);
 
-        let args = match Self::parse_args(rem_args, arg_spec) {
+        // global args before the subcommand cannot be taken for one of its
+        // own switches with the same name
+        let tag_words = subcommand == Subcommand::Modify;
+        let parsed =
+            Self::parse_args(tokens[1..position].to_vec(), &[], false).and_then(|mut args| {
+                args.extend(Self::parse_args(rem_args, arg_spec, tag_words)?);
+                Ok(args)
+            });
+
+        let args = match parsed {
             Ok(args) => args,
             Err(ParseError::Missing(arg)) => {
                 return Err(format!("switch \"{arg}\" requires a value").into());
@@ -186,16 +520,22 @@ impl CommandLine {
     }
 
     // TODO: Refactor this piece of shit
+    /// With `tag_words`, `-tag` is a value removing a tag rather than a
+    /// bundle of short switches, unless it is a single known one, and a
+    /// lone `--` is a value ending the hashes
     fn parse_args(
         tokens: Vec<String>,
         arg_spec: &'static [ArgSpec],
+        tag_words: bool,
     ) -> Result<Vec<Arg>, ParseError> {
         let flags: Vec<&ArgSpec> = arg_spec
             .iter()
+            .chain(GLOBAL_ARGS)
             .filter(|f| f.kind == ArgKind::Flag)
             .collect();
         let options: Vec<&ArgSpec> = arg_spec
             .iter()
+            .chain(GLOBAL_ARGS)
             .filter(|o| o.kind == ArgKind::Option)
             .collect();
 
@@ -204,7 +544,9 @@ impl CommandLine {
 
         while i < tokens.len() {
             let token = &tokens[i];
-            if let Some(arg_name) = token.strip_prefix("--") {
+            if tag_words && token == "--" {
+                args.push(Arg::Value(token.to_string()));
+            } else if let Some(arg_name) = token.strip_prefix("--") {
                 match flags.iter().find(|flag| flag.long == arg_name) {
                     Some(str) => {
                         args.push(Arg::Flag(str.name.to_string()));
@@ -227,13 +569,22 @@ impl CommandLine {
                         None => return Err(ParseError::UnknownArg(arg_name.to_string())),
                     },
                 }
+            } else if tag_words
+                && let Some(tag) = token.strip_prefix('-')
+                && todo::validate_tag(tag).is_ok()
+                && !arg_spec
+                    .iter()
+                    .chain(GLOBAL_ARGS)
+                    .any(|spec| spec.short.is_some_and(|short| tag.chars().eq([short])))
+            {
+                args.push(Arg::Value(token.to_string()));
             } else if let Some(arg) = token.strip_prefix("-") {
                 for a in arg.chars() {
-                    match flags.iter().find(|flag| flag.short == a) {
+                    match flags.iter().find(|flag| flag.short == Some(a)) {
                         Some(str) => {
                             args.push(Arg::Flag(str.name.to_string()));
                         }
-                        None => match options.iter().find(|opt| opt.short == a) {
+                        None => match options.iter().find(|opt| opt.short == Some(a)) {
                             Some(str) => {
                                 let next_token = tokens.get(i + 1);
 
@@ -261,13 +612,30 @@ impl CommandLine {
     }
 }
 
+pub fn read_stdin() -> Option<Vec<String>> {
+    if !io::stdin().is_terminal() {
+        let stdin = io::stdin();
+        let reader = BufReader::new(stdin.lock());
+
+        return Some(
+            reader
+                .lines() // TODO: Currently parsing with lines. To implement a parser similar to the Unix one
+                .map(|line| line.unwrap_or_default().trim().to_string())
+                .filter(|line| !line.is_empty()) // Skip empty lines
+                .collect(),
+        );
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cli::{Arg, CommandLine, Subcommand};
 
     #[test]
     fn get_args_long() {
-        let input = vec!["marc", "add", "--tag", "test", "should work"]
+        let input = ["marc", "add", "--tag", "test", "should work"]
             .iter()
             .map(|e| e.to_string())
             .collect();
@@ -290,7 +658,7 @@ mod tests {
 
     #[test]
     fn get_args_short() {
-        let input = vec!["marc", "add", "-t", "test", "should work"]
+        let input = ["marc", "add", "-t", "test", "should work"]
             .iter()
             .map(|e| e.to_string())
             .collect();
@@ -313,7 +681,7 @@ mod tests {
 
     #[test]
     fn get_args_concatenated() {
-        let input = vec!["marc", "log", "-ud"]
+        let input = ["marc", "log", "-ud"]
             .iter()
             .map(|e| e.to_string())
             .collect();
@@ -332,41 +700,140 @@ mod tests {
     }
 
     #[test]
-    fn err_on_unknow_args() {
-        let input = vec!["marc", "log", "--pippo"]
+    fn global_args_before_and_after_subcommand() {
+        let input = ["marc", "--file", "a.json", "log", "-d", "--file", "b.json"]
             .iter()
             .map(|e| e.to_string())
             .collect();
 
         let cmd_line = CommandLine::new(input);
-        assert!(cmd_line.is_err());
+
+        let crt_cmd_line = CommandLine {
+            subcommand: Subcommand::Log,
+            args: vec![
+                Arg::Option {
+                    name: "file".to_string(),
+                    value: "a.json".to_string(),
+                },
+                Arg::Flag("done".to_string()),
+                Arg::Option {
+                    name: "file".to_string(),
+                    value: "b.json".to_string(),
+                },
+            ],
+        };
+
+        assert_eq!(cmd_line.unwrap(), crt_cmd_line);
     }
 
     #[test]
-    fn err_on_missing_values() {
-        let input = vec!["marc", "add", "--tag"]
+    fn global_flag_before_subcommand() {
+        let input = ["marc", "--global", "--list", "work", "log"]
             .iter()
             .map(|e| e.to_string())
             .collect();
 
-        let cmd_line = CommandLine::new(input);
-        assert!(cmd_line.is_err());
+        let cmd_line = CommandLine::new(input).unwrap();
+
+        assert_eq!(cmd_line.subcommand, Subcommand::Log);
+        assert_eq!(
+            cmd_line.args,
+            [
+                Arg::Flag("global".to_string()),
+                Arg::Option {
+                    name: "list".to_string(),
+                    value: "work".to_string(),
+                },
+            ]
+        );
     }
-}
 
-pub fn read_stdin() -> Option<Vec<String>> {
-    if !io::stdin().is_terminal() {
-        let stdin = io::stdin();
-        let reader = BufReader::new(stdin.lock());
+    #[test]
+    fn global_option_before_subcommand_with_same_long_name() {
+        let input = ["marc", "--list", "work", "undo", "--list"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
 
-        return Some(
-            reader
-                .lines() // TODO: Currently parsing with lines. To implement a parser similar to the Unix one
-                .map(|line| line.unwrap_or_default().trim().to_string())
-                .filter(|line| !line.is_empty()) // Skip empty lines
-                .collect(),
+        let cmd_line = CommandLine::new(input).unwrap();
+
+        assert_eq!(
+            cmd_line.args,
+            [
+                Arg::Option {
+                    name: "list".to_string(),
+                    value: "work".to_string(),
+                },
+                Arg::Flag("preview".to_string()),
+            ]
         );
     }
 
-    None
+    #[test]
+    fn undone_is_reopen_with_filters() {
+        let input = ["marc", "undone", "-t", "work", "--yes"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+
+        let cmd_line = CommandLine::new(input).unwrap();
+
+        assert_eq!(cmd_line.subcommand, Subcommand::Reopen);
+        assert_eq!(
+            cmd_line.args,
+            [
+                Arg::Option {
+                    name: "tag".to_string(),
+                    value: "work".to_string(),
+                },
+                Arg::Flag("yes".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn modify_takes_tag_removals_as_values() {
+        let input = ["marc", "modify", "1a2b", "-work", "-p", "H", "+home", "--"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+
+        let cmd_line = CommandLine::new(input).unwrap();
+
+        assert_eq!(
+            cmd_line.args,
+            [
+                Arg::Value("1a2b".to_string()),
+                Arg::Value("-work".to_string()),
+                Arg::Option {
+                    name: "priority".to_string(),
+                    value: "H".to_string(),
+                },
+                Arg::Value("+home".to_string()),
+                Arg::Value("--".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn err_on_unknow_args() {
+        let input = ["marc", "log", "--pippo"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+
+        let cmd_line = CommandLine::new(input);
+        assert!(cmd_line.is_err());
+    }
+
+    #[test]
+    fn err_on_missing_values() {
+        let input = ["marc", "add", "--tag"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+
+        let cmd_line = CommandLine::new(input);
+        assert!(cmd_line.is_err());
+    }
 }
@@ -0,0 +1,956 @@
+use crate::atomic;
+use crate::status::Status;
+use crate::store::{JsonStore, Store};
+use crate::urgency::Weights;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+mod file;
+
+const APP_DIR_NAME: &str = "marc";
+const DB_FILE_NAME: &str = "marc.json";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const LISTS_DIR_NAME: &str = "lists";
+pub const PROJECT_DIR_NAME: &str = ".marc";
+pub const PROJECT_FILE_NAME: &str = ".marc.json";
+
+/// The list kept in `marc.json` itself, which always exists
+pub const DEFAULT_LIST: &str = "default";
+
+/// Which `Store` implementation holds the todo list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Backend::Json),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!("unknown backend \"{s}\"")),
+        }
+    }
+}
+
+/// The type of value a setting holds
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Count { min: u64, max: u64 },
+    Seconds,
+    Number,
+    TagWeights,
+    Statuses,
+    Choice(&'static [&'static str]),
+}
+
+impl Kind {
+    fn check(&self, value: &str) -> Result<(), String> {
+        match self {
+            Kind::Text => Ok(()),
+            Kind::Count { min, max } => match value.parse::<u64>() {
+                Ok(n) if (*min..=*max).contains(&n) => Ok(()),
+                _ => Err(format!("expected a whole number from {min} to {max}")),
+            },
+            Kind::Seconds => match value.parse::<f64>() {
+                Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(()),
+                _ => Err("expected a number of seconds".to_string()),
+            },
+            Kind::Number => match value.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(()),
+                _ => Err("expected a number".to_string()),
+            },
+            Kind::TagWeights => Weights::parse_tags(value).map(|_| ()),
+            Kind::Statuses => Status::parse_custom(value).map(|_| ()),
+            Kind::Choice(choices) if choices.contains(&value) => Ok(()),
+            Kind::Choice(choices) => Err(format!("expected one of {}", choices.join(", "))),
+        }
+    }
+
+    /// Formats a valid value as it is written in `config.toml`
+    fn literal(&self, value: &str) -> String {
+        match self {
+            Kind::Count { .. } | Kind::Seconds | Kind::Number => value.to_string(),
+            Kind::Text | Kind::TagWeights | Kind::Statuses | Kind::Choice(_) => file::quote(value),
+        }
+    }
+}
+
+/// A setting marc understands
+#[derive(Debug)]
+pub struct Key {
+    pub name: &'static str,
+    pub env: &'static str,
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+    kind: Kind,
+}
+
+/// Every setting, in the order `marc config list` shows them
+pub const KEYS: &[Key] = &[
+    Key {
+        name: "core.backend",
+        env: "MARC_BACKEND",
+        default: Some("json"),
+        description: "where the list is stored",
+        kind: Kind::Choice(&["json", "sqlite"]),
+    },
+    Key {
+        name: "core.lock_timeout",
+        env: "MARC_LOCK_TIMEOUT",
+        default: Some("10"),
+        description: "seconds to wait for another marc process to finish",
+        kind: Kind::Seconds,
+    },
+    Key {
+        name: "core.default_list",
+        env: "MARC_DEFAULT_LIST",
+        default: Some(DEFAULT_LIST),
+        description: "list used when --list is not given",
+        kind: Kind::Text,
+    },
+    Key {
+        name: "add.default_tag",
+        env: "MARC_DEFAULT_TAG",
+        default: Some("default"),
+        description: "tag for todos added without --tag, empty for none",
+        kind: Kind::Text,
+    },
+    Key {
+        name: "edit.editor",
+        env: "MARC_EDITOR",
+        default: None,
+        description: "editor for 'marc edit', $EDITOR or vim when unset",
+        kind: Kind::Text,
+    },
+    Key {
+        name: "log.color",
+        env: "MARC_COLOR",
+        default: Some("auto"),
+        description: "colored output, 'auto' only colors a terminal",
+        kind: Kind::Choice(&["auto", "always", "never"]),
+    },
+    Key {
+        name: "log.sort",
+        env: "MARC_SORT",
+        default: Some("urgency"),
+        description: "order of 'marc log', most urgent first or as added",
+        kind: Kind::Choice(&["urgency", "added"]),
+    },
+    Key {
+        name: "hash.length",
+        env: "MARC_HASH_LENGTH",
+        default: Some("7"),
+        description: "number of characters in new todo hashes",
+        kind: Kind::Count {
+            min: crate::todo::MIN_HASH_LENGTH as u64,
+            max: 16,
+        },
+    },
+    Key {
+        name: "undo.depth",
+        env: "MARC_UNDO_DEPTH",
+        default: Some("50"),
+        description: "number of operations 'marc undo' can revert",
+        kind: Kind::Count { min: 1, max: 10000 },
+    },
+    Key {
+        name: "status.custom",
+        env: "MARC_STATUS_CUSTOM",
+        default: Some(""),
+        description: "states open todos can be in besides started and waiting, like 'review'",
+        kind: Kind::Statuses,
+    },
+    Key {
+        name: "subtasks.complete",
+        env: "MARC_SUBTASKS_COMPLETE",
+        default: Some("block"),
+        description: "completing a todo with open subtasks is refused or completes them",
+        kind: Kind::Choice(&["block", "cascade"]),
+    },
+    Key {
+        name: "subtasks.remove",
+        env: "MARC_SUBTASKS_REMOVE",
+        default: Some("reparent"),
+        description: "removing a todo moves its subtasks up or removes them too",
+        kind: Kind::Choice(&["reparent", "cascade"]),
+    },
+    Key {
+        name: "urgency.priority",
+        env: "MARC_URGENCY_PRIORITY",
+        default: Some("6"),
+        description: "urgency of a high priority todo",
+        kind: Kind::Number,
+    },
+    Key {
+        name: "urgency.due",
+        env: "MARC_URGENCY_DUE",
+        default: Some("12"),
+        description: "urgency of a todo a week overdue",
+        kind: Kind::Number,
+    },
+    Key {
+        name: "urgency.age",
+        env: "MARC_URGENCY_AGE",
+        default: Some("2"),
+        description: "urgency of a todo a year old",
+        kind: Kind::Number,
+    },
+    Key {
+        name: "urgency.tags",
+        env: "MARC_URGENCY_TAGS",
+        default: Some(""),
+        description: "urgency added per tag, like 'work=2,someday=-3'",
+        kind: Kind::TagWeights,
+    },
+];
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    File { path: PathBuf, line: usize },
+    Env(&'static str),
+    Flag,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File { path, line } => write!(f, "{}:{line}", path.display()),
+            Origin::Env(var) => write!(f, "${var}"),
+            Origin::Flag => write!(f, "--config"),
+        }
+    }
+}
+
+/// Effective value of every setting, layered as defaults, then the config
+/// file, then environment variables, then `--config key=value` flags
+#[derive(Debug, Clone)]
+pub struct Settings {
+    values: BTreeMap<&'static str, (String, Origin)>,
+}
+
+impl Settings {
+    fn defaults() -> Self {
+        let values = KEYS
+            .iter()
+            .filter_map(|key| {
+                key.default
+                    .map(|value| (key.name, (value.to_string(), Origin::Default)))
+            })
+            .collect();
+
+        Settings { values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&(String, Origin)> {
+        self.values.get(name)
+    }
+
+    fn set(&mut self, name: &str, value: String, origin: Origin) -> Result<(), String> {
+        let key = find_key(name)?;
+        key.kind
+            .check(&value)
+            .map_err(|e| format!("invalid value '{value}' for '{name}': {e}"))?;
+        self.values.insert(key.name, (value, origin));
+        Ok(())
+    }
+
+    fn apply_file(&mut self, path: &Path, source: &str) -> Result<(), Box<dyn Error>> {
+        let entries = file::parse(source)
+            .map_err(|(line, e)| format!("error: {}:{line}: {e}", path.display()))?;
+
+        for entry in entries {
+            if find_key(&entry.key).is_err() {
+                eprintln!(
+                    "warning: {}:{}: unknown key '{}'",
+                    path.display(),
+                    entry.line,
+                    entry.key
+                );
+                continue;
+            }
+
+            let origin = Origin::File {
+                path: path.to_path_buf(),
+                line: entry.line,
+            };
+            self.set(&entry.key, entry.value, origin)
+                .map_err(|e| format!("error: {}:{}: {e}", path.display(), entry.line))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        for key in KEYS {
+            if let Ok(value) = env::var(key.env) {
+                self.set(key.name, value, Origin::Env(key.env))
+                    .map_err(|e| format!("error: ${}: {e}", key.env))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|(value, _)| value.as_str())
+    }
+}
+
+fn find_key(name: &str) -> Result<&'static Key, String> {
+    KEYS.iter()
+        .find(|key| key.name == name)
+        .ok_or_else(|| format!("unknown key '{name}'"))
+}
+
+/// Todos kept with a project, found by walking up from the working
+/// directory the way git finds `.git`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Project {
+    /// A `.marc/` directory, holding lists like the data directory does
+    Dir(PathBuf),
+    /// A `.marc.json` file, a single list
+    File(PathBuf),
+}
+
+impl Project {
+    /// The project of `start` or of its nearest ancestor that has one
+    pub fn discover(start: &Path) -> Option<Project> {
+        start.ancestors().find_map(|dir| {
+            let project_dir = dir.join(PROJECT_DIR_NAME);
+            let project_file = dir.join(PROJECT_FILE_NAME);
+
+            if project_dir.is_dir() {
+                Some(Project::Dir(project_dir))
+            } else if project_file.is_file() {
+                Some(Project::File(project_file))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub backend: Backend,
+    /// Todo file to use instead of the one in the data directory
+    pub file: Option<PathBuf>,
+    /// Directory to keep the lists in instead of the data directory
+    pub dir: Option<PathBuf>,
+    /// List picked with `--list`
+    pub list: Option<String>,
+    /// Ignore project lists, set with `--global`
+    pub global: bool,
+    pub default_list: String,
+    pub lock_timeout: Duration,
+    /// Tag for todos added without one
+    pub default_tag: Option<String>,
+    pub editor: Option<String>,
+    pub color: bool,
+    pub hash_length: usize,
+    pub undo_depth: usize,
+    /// Sort `marc log` by urgency rather than in list order
+    pub sort_by_urgency: bool,
+    /// Completing a todo completes its open subtasks rather than being
+    /// refused
+    pub cascade_completion: bool,
+    /// Removing a todo removes its subtasks rather than moving them up
+    pub cascade_removal: bool,
+    /// States from `status.custom`
+    pub custom_statuses: Vec<String>,
+    /// Questions can be asked, stdin being a terminal
+    pub interactive: bool,
+    pub urgency: Weights,
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Reads the configuration file and the environment.
+    ///
+    /// `MARC_FILE` points at a todo file, `MARC_DIR` at a directory holding
+    /// the lists; both take precedence over the XDG data directory.
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        Self::load_with(&[])
+    }
+
+    /// Like `load`, with `key=value` overrides taking precedence over
+    /// everything else
+    pub fn load_with(overrides: &[String]) -> Result<Config, Box<dyn Error>> {
+        let mut settings = Settings::defaults();
+
+        let config_path = Self::config_path()?;
+        if config_path.exists() {
+            let source = fs::read_to_string(&config_path).map_err(|e| {
+                format!(
+                    "error: failed to read config file ({}): {e}",
+                    config_path.display()
+                )
+            })?;
+            settings.apply_file(&config_path, &source)?;
+        }
+
+        settings.apply_env()?;
+
+        for setting in overrides {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("error: --config expects key=value, got '{setting}'"))?;
+            settings
+                .set(name.trim(), value.to_string(), Origin::Flag)
+                .map_err(|e| format!("error: --config: {e}"))?;
+        }
+
+        let mut config = Self::from_settings(settings)?;
+        config.file = env_var("MARC_FILE").map(PathBuf::from);
+        config.dir = env_var("MARC_DIR").map(PathBuf::from);
+        config.interactive = io::stdin().is_terminal();
+        Ok(config)
+    }
+
+    /// The defaults alone, without colors or questions, whatever the
+    /// environment
+    #[cfg(test)]
+    pub(crate) fn defaults() -> Config {
+        let mut config = Self::from_settings(Settings::defaults()).unwrap();
+        config.color = false;
+        config
+    }
+
+    fn from_settings(settings: Settings) -> Result<Config, Box<dyn Error>> {
+        // Every value was checked by `Settings::set`
+        let value = |name| settings.value(name).unwrap_or_default();
+        let backend = Backend::from_str(value("core.backend"))?;
+        let lock_timeout = Duration::from_secs_f64(value("core.lock_timeout").parse()?);
+        let default_list = value("core.default_list").to_string();
+        let default_tag = Some(value("add.default_tag").to_string()).filter(|t| !t.is_empty());
+        let editor = settings.value("edit.editor").map(str::to_string);
+        let color = match value("log.color") {
+            "always" => true,
+            "never" => false,
+            _ => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        };
+        let hash_length = value("hash.length").parse()?;
+        let undo_depth = value("undo.depth").parse()?;
+        let sort_by_urgency = value("log.sort") == "urgency";
+        let cascade_completion = value("subtasks.complete") == "cascade";
+        let cascade_removal = value("subtasks.remove") == "cascade";
+        let custom_statuses = Status::parse_custom(value("status.custom"))?;
+        let urgency = Weights {
+            priority: value("urgency.priority").parse()?,
+            due: value("urgency.due").parse()?,
+            age: value("urgency.age").parse()?,
+            tags: Weights::parse_tags(value("urgency.tags"))?,
+        };
+
+        Ok(Config {
+            backend,
+            file: None,
+            dir: None,
+            list: None,
+            global: false,
+            default_list,
+            lock_timeout,
+            default_tag,
+            editor,
+            color,
+            hash_length,
+            undo_depth,
+            sort_by_urgency,
+            cascade_completion,
+            cascade_removal,
+            custom_statuses,
+            interactive: false,
+            urgency,
+            settings,
+        })
+    }
+
+    /// `$MARC_CONFIG`, or `config.toml` in the config directory
+    pub fn config_path() -> Result<PathBuf, Box<dyn Error>> {
+        match env_var("MARC_CONFIG") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(Self::config_dir()?.join(CONFIG_FILE_NAME)),
+        }
+    }
+
+    /// Validates `value` and writes it to the config file
+    pub fn write_setting(name: &str, value: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let key = find_key(name)?;
+        key.kind
+            .check(value)
+            .map_err(|e| format!("invalid value '{value}' for '{name}': {e}"))?;
+
+        let path = Self::config_path()?;
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let updated = file::set(&source, key.name, &key.kind.literal(value));
+        atomic::write(&path, updated.as_bytes()).map_err(|e| {
+            format!(
+                "error: failed to write config file ({}): {e}",
+                path.display()
+            )
+        })?;
+
+        Ok(path)
+    }
+
+    /// Opens the store selected by the configuration
+    pub fn open_store(&self) -> Result<Box<dyn Store>, Box<dyn Error>> {
+        self.open_backend(self.backend)
+    }
+
+    /// Opens the todo list in the given backend
+    pub fn open_backend(&self, backend: Backend) -> Result<Box<dyn Store>, Box<dyn Error>> {
+        self.open_at(backend, self.path()?)
+    }
+
+    /// Opens a named list, regardless of `--list` and `--file`. The list is
+    /// created on its first save if it does not exist.
+    pub fn open_list(&self, name: &str) -> Result<Box<dyn Store>, Box<dyn Error>> {
+        self.open_at(self.backend, self.list_path(name)?)
+    }
+
+    /// Opens a backend on `path`, the JSON file name of a list
+    fn open_at(&self, backend: Backend, path: PathBuf) -> Result<Box<dyn Store>, Box<dyn Error>> {
+        match backend {
+            Backend::Json => Ok(Box::new(JsonStore::new(path, self.lock_timeout))),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => Ok(Box::new(crate::store::SqliteStore::open(
+                path.with_extension("db"),
+                self.lock_timeout,
+            )?)),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => Err(
+                "error: marc was built without SQLite support (enable the 'sqlite' feature)".into(),
+            ),
+        }
+    }
+
+    /// The project found from the working directory, unless `--global`,
+    /// `MARC_FILE` or `MARC_DIR` say where the todos are
+    pub fn project(&self) -> Option<Project> {
+        if self.global || self.file.is_some() || self.dir.is_some() {
+            return None;
+        }
+
+        Project::discover(&env::current_dir().ok()?)
+    }
+
+    /// The single todo file in use instead of named lists, if any
+    pub fn single_file(&self) -> Option<PathBuf> {
+        match (&self.file, self.project()) {
+            (Some(file), _) => Some(file.clone()),
+            (None, Some(Project::File(file))) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// The todo file in use, honouring `--file`, `MARC_FILE`, `--list`,
+    /// `MARC_DIR` and project lists
+    pub fn path(&self) -> Result<PathBuf, Box<dyn Error>> {
+        if let Some(file) = &self.single_file() {
+            if self.list.is_some() {
+                return Err(format!(
+                    "error: --list cannot be combined with a single todo file ({}), use --global for your own lists",
+                    file.display()
+                )
+                .into());
+            }
+            return Ok(file.clone());
+        }
+
+        let name = self.list_name();
+        if !self.list_exists(name)? {
+            return Err(Self::missing_list(name));
+        }
+        self.list_path(name)
+    }
+
+    /// Name of the list commands work on
+    pub fn list_name(&self) -> &str {
+        self.list.as_deref().unwrap_or(&self.default_list)
+    }
+
+    /// JSON file name of a list, whether it exists or not. Nothing is
+    /// created here; directories appear when the list is first saved.
+    pub fn list_path(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        validate_list_name(name)?;
+
+        let root = match (&self.dir, self.project()) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(Project::Dir(dir))) => dir,
+            _ => Self::data_root()?,
+        };
+
+        if name == DEFAULT_LIST {
+            Ok(root.join(DB_FILE_NAME))
+        } else {
+            Ok(root.join(LISTS_DIR_NAME).join(format!("{name}.json")))
+        }
+    }
+
+    pub fn list_exists(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        if name == DEFAULT_LIST {
+            return Ok(true);
+        }
+
+        let path = self.list_path(name)?;
+        Ok(match self.backend {
+            Backend::Sqlite => path.with_extension("db").exists(),
+            _ => path.exists(),
+        })
+    }
+
+    /// Every list of the current backend, the default one first
+    pub fn list_names(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let extension = match self.backend {
+            Backend::Sqlite => "db",
+            _ => "json",
+        };
+
+        let mut names = Vec::new();
+        let lists_dir = self.list_path(DEFAULT_LIST)?.with_file_name(LISTS_DIR_NAME);
+
+        if lists_dir.exists() {
+            for entry in fs::read_dir(&lists_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == extension)
+                    && let Some(name) = path.file_stem().and_then(|n| n.to_str())
+                    && validate_list_name(name).is_ok()
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        names.insert(0, DEFAULT_LIST.to_string());
+        Ok(names)
+    }
+
+    /// The files of a list in every backend, with their lock files and
+    /// migration backups
+    pub fn list_files(&self, name: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let path = self.list_path(name)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let names = [path.clone(), path.with_extension("db")].map(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+
+        let mut files = Vec::new();
+        if dir.exists() {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+
+                if names
+                    .iter()
+                    .any(|n| *file_name == **n || file_name.starts_with(&format!("{n}.")))
+                {
+                    files.push(entry.path());
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    pub fn missing_list(name: &str) -> Box<dyn Error> {
+        format!("error: list '{name}' does not exist, create it with 'marc list create {name}'")
+            .into()
+    }
+
+    /// The default list's file in the data directory
+    pub fn get_path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(Self::data_root()?.join(DB_FILE_NAME))
+    }
+
+    /// The data directory, filled from the legacy `~/marc` directory if there
+    /// is one
+    fn data_root() -> Result<PathBuf, Box<dyn Error>> {
+        let data_dir = Self::data_dir()?;
+        let legacy_dir = Self::home_dir()?.join(APP_DIR_NAME);
+
+        if legacy_dir != data_dir && Self::move_legacy_dir(&legacy_dir, &data_dir)? {
+            eprintln!(
+                "marc: moved your todo list from {} to {}",
+                legacy_dir.display(),
+                data_dir.display()
+            );
+        }
+
+        Ok(data_dir)
+    }
+
+    /// `$XDG_DATA_HOME/marc`, `~/.local/share/marc` by default
+    pub fn data_dir() -> Result<PathBuf, Box<dyn Error>> {
+        xdg_dir(env_var, "XDG_DATA_HOME", &[".local", "share"])
+    }
+
+    /// `$XDG_CONFIG_HOME/marc`, `~/.config/marc` by default
+    pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+        xdg_dir(env_var, "XDG_CONFIG_HOME", &[".config"])
+    }
+
+    fn home_dir() -> Result<PathBuf, Box<dyn Error>> {
+        home_dir(env_var)
+    }
+
+    /// Moves every file of the legacy directory into `data_dir`, unless the
+    /// data directory already holds a todo file. Returns whether it did.
+    fn move_legacy_dir(legacy_dir: &Path, data_dir: &Path) -> io::Result<bool> {
+        if !legacy_dir.join(DB_FILE_NAME).exists() || data_dir.join(DB_FILE_NAME).exists() {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(data_dir)?;
+
+        for entry in fs::read_dir(legacy_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let from = entry.path();
+            let to = data_dir.join(entry.file_name());
+            if to.exists() {
+                continue;
+            }
+
+            // The data directory may live on another filesystem
+            if fs::rename(&from, &to).is_err() {
+                fs::copy(&from, &to)?;
+                fs::remove_file(&from)?;
+            }
+        }
+
+        // Only succeeds if nothing else was left behind
+        let _ = fs::remove_dir(legacy_dir);
+
+        Ok(true)
+    }
+}
+
+/// List names end up in file names, so they are kept to a safe alphabet
+pub fn validate_list_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid list name '{name}', use letters, digits, '-' and '_'"
+        ))
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn home_dir(var: impl Fn(&str) -> Option<String>) -> Result<PathBuf, Box<dyn Error>> {
+    var("HOME")
+        .or_else(|| var("USERPROFILE"))
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "home directory not set").into())
+}
+
+/// Resolves an XDG base directory for marc. Relative values are ignored, as
+/// the specification requires.
+fn xdg_dir(
+    var: impl Fn(&str) -> Option<String>,
+    xdg_var: &str,
+    fallback: &[&str],
+) -> Result<PathBuf, Box<dyn Error>> {
+    let base = match var(xdg_var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => fallback
+            .iter()
+            .fold(home_dir(&var)?, |dir, part| dir.join(part)),
+    };
+
+    Ok(base.join(APP_DIR_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| {
+            pairs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        }
+    }
+
+    #[test]
+    fn xdg_dir_prefers_absolute_xdg_variable() {
+        let var = vars(&[("HOME", "/home/me"), ("XDG_DATA_HOME", "/data")]);
+
+        let dir = xdg_dir(var, "XDG_DATA_HOME", &[".local", "share"]).unwrap();
+
+        assert_eq!(dir, PathBuf::from("/data/marc"));
+    }
+
+    #[test]
+    fn xdg_dir_falls_back_to_home() {
+        let var = vars(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "relative")]);
+
+        let dir = xdg_dir(var, "XDG_CONFIG_HOME", &[".config"]).unwrap();
+
+        assert_eq!(dir, PathBuf::from("/home/me/.config/marc"));
+    }
+
+    #[test]
+    fn settings_layer_file_over_defaults_and_flag_over_file() {
+        let path = PathBuf::from("config.toml");
+        let mut settings = Settings::defaults();
+
+        settings
+            .apply_file(
+                &path,
+                "[hash]\nlength = 9\n[add]\ndefault_tag = \"inbox\"\n",
+            )
+            .unwrap();
+        settings
+            .set("hash.length", "12".to_string(), Origin::Flag)
+            .unwrap();
+
+        assert_eq!(
+            settings.get("add.default_tag"),
+            Some(&("inbox".to_string(), Origin::File { path, line: 4 }))
+        );
+        assert_eq!(
+            settings.get("hash.length"),
+            Some(&("12".to_string(), Origin::Flag))
+        );
+        assert_eq!(
+            settings.get("log.color"),
+            Some(&("auto".to_string(), Origin::Default))
+        );
+    }
+
+    #[test]
+    fn settings_reject_invalid_values_with_line_number() {
+        let path = PathBuf::from("config.toml");
+        let mut settings = Settings::defaults();
+
+        let err = settings
+            .apply_file(&path, "# marc\n[hash]\nlength = 99\n")
+            .unwrap_err();
+
+        assert!(err.to_string().contains("config.toml:3"), "{err}");
+    }
+
+    #[test]
+    fn the_memory_store_is_not_a_backend() {
+        let mut settings = Settings::defaults();
+
+        let err = settings
+            .apply_file(
+                Path::new("config.toml"),
+                "[core]
+backend = \"memory\"\n",
+            )
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("expected one of json, sqlite"),
+            "{err}"
+        );
+        assert!("memory".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn project_is_found_in_the_nearest_ancestor() {
+        let root = TempDir::new().unwrap();
+        let nested = root.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(root.path().join(PROJECT_DIR_NAME)).unwrap();
+        fs::write(root.path().join("a").join(PROJECT_FILE_NAME), "").unwrap();
+
+        assert_eq!(
+            Project::discover(&nested),
+            Some(Project::File(root.path().join("a").join(PROJECT_FILE_NAME)))
+        );
+        assert_eq!(
+            Project::discover(root.path()),
+            Some(Project::Dir(root.path().join(PROJECT_DIR_NAME)))
+        );
+    }
+
+    #[test]
+    fn list_names_must_be_safe_file_names() {
+        assert!(validate_list_name("work-2").is_ok());
+        assert!(validate_list_name("../etc").is_err());
+        assert!(validate_list_name("").is_err());
+    }
+
+    #[test]
+    fn resolving_lists_creates_nothing() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path().join("lists");
+        let config = Config {
+            dir: Some(dir.clone()),
+            ..Config::defaults()
+        };
+
+        assert_eq!(
+            config.list_path("work").unwrap(),
+            dir.join(LISTS_DIR_NAME).join("work.json")
+        );
+        assert_eq!(config.list_names().unwrap(), [DEFAULT_LIST]);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn legacy_dir_is_moved_once() {
+        let home = TempDir::new().unwrap();
+        let legacy = home.path().join("marc");
+        let data = home.path().join(".local/share/marc");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join(DB_FILE_NAME), "{}").unwrap();
+
+        assert!(Config::move_legacy_dir(&legacy, &data).unwrap());
+        assert!(data.join(DB_FILE_NAME).exists());
+        assert!(!legacy.exists());
+
+        assert!(!Config::move_legacy_dir(&legacy, &data).unwrap());
+    }
+}
@@ -0,0 +1,265 @@
+//! Reading and updating `config.toml`.
+//!
+//! Only the part of TOML marc's settings need is supported: `[section]`
+//! headers and `key = value` lines holding a string, number or boolean.
+
+/// A `key = value` line, with the key qualified by its section
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+/// Parses the file into its entries. Errors carry the 1-based line number.
+pub fn parse(source: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = strip_comment(header);
+            section = header
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| is_bare_key(name))
+                .ok_or((number, format!("invalid section header '{line}'")))?
+                .to_string();
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or((number, format!("expected 'key = value', found '{line}'")))?;
+        let name = name.trim();
+
+        if !is_bare_key(name) {
+            return Err((number, format!("invalid key '{name}'")));
+        }
+
+        let value = parse_value(value.trim()).map_err(|e| (number, e))?;
+
+        let key = if section.is_empty() {
+            name.to_string()
+        } else {
+            format!("{section}.{name}")
+        };
+
+        entries.push(Entry {
+            key,
+            value,
+            line: number,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Returns `source` with `key` set to the TOML `literal`, keeping every
+/// other line, comments included, as it was
+pub fn set(source: &str, key: &str, literal: &str) -> String {
+    let (section, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let new_line = format!("{name} = {literal}");
+
+    let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+    let mut current = String::new();
+    let mut insert_at = if section.is_empty() { Some(0) } else { None };
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+
+        if let Some(header) = trimmed.strip_prefix('[') {
+            current = strip_comment(header)
+                .trim_end_matches(']')
+                .trim()
+                .to_string();
+            if current == section {
+                insert_at = Some(i + 1);
+            }
+            continue;
+        }
+
+        if current != section {
+            continue;
+        }
+
+        match trimmed.split_once('=') {
+            Some((existing, _)) if existing.trim() == name => {
+                *line = new_line;
+                return join(lines);
+            }
+            _ if !trimmed.is_empty() => insert_at = Some(i + 1),
+            _ => {}
+        }
+    }
+
+    match insert_at {
+        Some(i) => lines.insert(i, new_line),
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{section}]"));
+            lines.push(new_line);
+        }
+    }
+
+    join(lines)
+}
+
+/// Formats a string as a TOML basic string
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn join(lines: Vec<String>) -> String {
+    let mut joined = lines.join("\n");
+    joined.push('\n');
+    joined
+}
+
+fn is_bare_key(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split('#').next().unwrap_or_default().trim()
+}
+
+fn parse_value(raw: &str) -> Result<String, String> {
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => return Err(format!("unsupported escape '\\{c}'")),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some(c) => value.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+
+        return expect_end(chars.as_str()).map(|_| value);
+    }
+
+    if let Some(rest) = raw.strip_prefix('\'') {
+        let (value, rest) = rest
+            .split_once('\'')
+            .ok_or("unterminated string".to_string())?;
+        return expect_end(rest).map(|_| value.to_string());
+    }
+
+    let value = strip_comment(raw);
+    let is_number = value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok();
+
+    if value == "true" || value == "false" || is_number {
+        Ok(value.to_string())
+    } else if value.is_empty() {
+        Err("missing value".to_string())
+    } else {
+        Err(format!("invalid value '{value}' (strings must be quoted)"))
+    }
+}
+
+fn expect_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{rest}' after value"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_values() {
+        let source = "# marc settings\n\
+                      [add]\n\
+                      default_tag = \"inbox\" # comment\n\
+                      \n\
+                      [hash]\n\
+                      length = 8\n";
+
+        let entries = parse(source).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    key: "add.default_tag".to_string(),
+                    value: "inbox".to_string(),
+                    line: 3,
+                },
+                Entry {
+                    key: "hash.length".to_string(),
+                    value: "8".to_string(),
+                    line: 6,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_line_of_invalid_value() {
+        let source = "[edit]\neditor = nvim\n";
+
+        let (line, message) = parse(source).unwrap_err();
+
+        assert_eq!(line, 2);
+        assert!(message.contains("quoted"), "{message}");
+    }
+
+    #[test]
+    fn set_replaces_existing_key_and_keeps_comments() {
+        let source = "[add]\n# the tag\ndefault_tag = \"a\"\n";
+
+        let updated = set(source, "add.default_tag", "\"b\"");
+
+        assert_eq!(updated, "[add]\n# the tag\ndefault_tag = \"b\"\n");
+    }
+
+    #[test]
+    fn set_adds_missing_keys_and_sections() {
+        let source = "[add]\ndefault_tag = \"a\"\n\n[log]\ncolor = \"never\"\n";
+
+        let updated = set(source, "add.other", "1");
+        let updated = set(&updated, "hash.length", "8");
+
+        assert_eq!(
+            updated,
+            "[add]\ndefault_tag = \"a\"\nother = 1\n\n[log]\ncolor = \"never\"\n\n[hash]\nlength = 8\n"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Timestamps, kept as seconds since the Unix epoch and shown in UTC, and
+//! calendar dates for due dates.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod parse;
+pub use parse::parse;
+pub(crate) use parse::weekday;
+
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The last year a date can have, as dates are written with four digits
+const LAST_YEAR: u64 = 9999;
+
+/// A day in the UTC calendar, from 1970-01-01 on. Written as `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Date {
+    /// Days since 1970-01-01
+    days: u64,
+}
+
+impl Date {
+    pub fn from_ymd(year: u64, month: u64, day: u64) -> Option<Date> {
+        if !(1970..=LAST_YEAR).contains(&year)
+            || !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+        {
+            return None;
+        }
+
+        Some(Date {
+            days: days_from_civil(year, month, day),
+        })
+    }
+
+    /// The day `timestamp` falls on
+    pub fn from_timestamp(timestamp: u64) -> Date {
+        Date {
+            days: timestamp / SECONDS_PER_DAY,
+        }
+    }
+
+    pub fn ymd(&self) -> (u64, u64, u64) {
+        civil_from_days(self.days)
+    }
+
+    /// Days since Monday, from 0 for Monday to 6 for Sunday
+    pub fn weekday(&self) -> u64 {
+        // 1970-01-01 was a Thursday
+        (self.days + 3) % 7
+    }
+
+    /// The day `days` later, if it is before the end of `LAST_YEAR`
+    pub fn add_days(&self, days: u64) -> Option<Date> {
+        self.days
+            .checked_add(days)
+            .filter(|days| *days <= days_from_civil(LAST_YEAR, 12, 31))
+            .map(|days| Date { days })
+    }
+
+    /// The same day `months` later, or the last day of that month if it is
+    /// shorter. Nothing past the end of `LAST_YEAR`.
+    pub fn add_months(&self, months: u64) -> Option<Date> {
+        let (year, month, day) = self.ymd();
+        let index = (year * 12 + (month - 1)).checked_add(months)?;
+        let (year, month) = (index / 12, index % 12 + 1);
+        if year > LAST_YEAR {
+            return None;
+        }
+
+        Some(Date {
+            days: days_from_civil(year, month, day.min(days_in_month(year, month))),
+        })
+    }
+
+    /// The given day of the same month, or its last day if it is shorter
+    pub fn with_day(&self, day: u64) -> Date {
+        let (year, month, _) = self.ymd();
+        Date {
+            days: days_from_civil(year, month, day.clamp(1, days_in_month(year, month))),
+        }
+    }
+
+    pub fn end_of_month(&self) -> Date {
+        let (year, month, _) = self.ymd();
+        Date {
+            days: days_from_civil(year, month, days_in_month(year, month)),
+        }
+    }
+
+    /// Days from `self` to `other`, negative if `other` is earlier
+    pub fn days_until(&self, other: Date) -> i64 {
+        other.days as i64 - self.days as i64
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{year:04}-{month:02}-{day:02}")
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid date '{s}', expected YYYY-MM-DD");
+        let parts: Vec<&str> = s.split('-').collect();
+
+        let [year, month, day] = parts.as_slice() else {
+            return Err(invalid());
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(invalid());
+        }
+
+        let number = |part: &str| part.parse::<u64>().map_err(|_| invalid());
+        Date::from_ymd(number(year)?, number(month)?, number(day)?).ok_or_else(invalid)
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> String {
+        date.to_string()
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The current time
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `YYYY-MM-DD HH:MM`
+pub fn format_datetime(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / SECONDS_PER_DAY);
+    let seconds = timestamp % SECONDS_PER_DAY;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// How long before `now` something happened, like `3d ago`
+pub fn relative(timestamp: u64, now: u64) -> String {
+    const UNITS: [(u64, &str); 6] = [
+        (365 * SECONDS_PER_DAY, "y"),
+        (30 * SECONDS_PER_DAY, "mo"),
+        (7 * SECONDS_PER_DAY, "w"),
+        (SECONDS_PER_DAY, "d"),
+        (3600, "h"),
+        (60, "m"),
+    ];
+
+    let elapsed = now.saturating_sub(timestamp);
+
+    UNITS
+        .iter()
+        .find(|(seconds, _)| elapsed >= *seconds)
+        .map(|(seconds, unit)| format!("{}{unit} ago", elapsed / seconds))
+        .unwrap_or_else(|| "just now".to_string())
+}
+
+fn is_leap_year(year: u64) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date from 1970 on, from Howard Hinnant's
+/// `days_from_civil`
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Year, month and day of the day `days` after 1970-01-01, from Howard
+/// Hinnant's `civil_from_days`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        assert_eq!(format_datetime(0), "1970-01-01 00:00");
+        assert_eq!(format_datetime(951_827_696), "2000-02-29 12:34");
+        assert_eq!(format_datetime(1_792_195_200), "2026-10-17 00:00");
+    }
+
+    #[test]
+    fn dates_round_trip_through_days() {
+        for s in ["1970-01-01", "2000-02-29", "2026-10-17", "2100-12-31"] {
+            assert_eq!(s.parse::<Date>().unwrap().to_string(), s);
+        }
+
+        assert!("2026-02-29".parse::<Date>().is_err());
+        assert!("2026-1-5".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn add_months_clamps_to_the_end_of_the_month() {
+        let date: Date = "2024-01-31".parse().unwrap();
+
+        assert_eq!(date.add_months(1).unwrap().to_string(), "2024-02-29");
+        assert_eq!(date.add_months(12).unwrap().to_string(), "2025-01-31");
+    }
+
+    #[test]
+    fn arithmetic_stops_at_the_last_year() {
+        let date: Date = "9999-12-01".parse().unwrap();
+
+        assert_eq!(date.add_days(30).unwrap().to_string(), "9999-12-31");
+        assert_eq!(date.add_days(31), None);
+        assert_eq!(date.add_days(u64::MAX), None);
+        assert_eq!(date.add_months(1), None);
+        assert_eq!(date.add_months(u64::MAX), None);
+    }
+
+    #[test]
+    fn relative_ages_use_the_largest_unit() {
+        let now = 1_792_195_200;
+
+        assert_eq!(relative(now - 30, now), "just now");
+        assert_eq!(relative(now - 3 * 3600 - 59, now), "3h ago");
+        assert_eq!(relative(now - 3 * SECONDS_PER_DAY, now), "3d ago");
+        assert_eq!(relative(now - 400 * SECONDS_PER_DAY, now), "1y ago");
+    }
+}
@@ -0,0 +1,147 @@
+//! Reading dates the way people write them on the command line.
+//!
+//! Everything is relative to `today`, which callers pass in so the results
+//! do not depend on when the tests run.
+
+use super::Date;
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Parses an ISO date (`2026-10-20`) or one of:
+///
+/// - `today`, `tomorrow`
+/// - a weekday (`fri`, `friday`): the next one after today
+/// - `next week`, `next month`, `next year`: the first day of it
+/// - `eow`, `eom`, `eoy`: the last day of this week, month or year
+/// - `+3d`, `+2w`, `+1m`, `+1y` or `in 3 days`: that long from today
+pub fn parse(input: &str, today: Date) -> Result<Date, String> {
+    let text = input.trim().to_lowercase();
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    let date = match words.as_slice() {
+        ["today" | "tod"] => Some(today),
+        ["tomorrow" | "tom"] => today.add_days(1),
+        ["eow"] => today.add_days(6 - today.weekday()),
+        ["eom"] => Some(today.end_of_month()),
+        ["eoy"] => Date::from_ymd(today.ymd().0, 12, 31),
+        ["next", "week"] => today.add_days(7 - today.weekday()),
+        ["next", "month"] => today.add_months(1).and_then(|date| {
+            let (year, month, _) = date.ymd();
+            Date::from_ymd(year, month, 1)
+        }),
+        ["next", "year"] => Date::from_ymd(today.ymd().0 + 1, 1, 1),
+        ["in", count, unit] => offset(today, count, unit),
+        [word] if word.starts_with('+') => {
+            let rest = &word[1..];
+            let (count, unit) = rest.split_at(
+                rest.find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len()),
+            );
+            offset(today, count, unit)
+        }
+        [word] => match weekday(word) {
+            Some(day) => today.add_days((day + 6 - today.weekday()) % 7 + 1),
+            None => word.parse().ok(),
+        },
+        _ => None,
+    };
+
+    date.ok_or_else(|| {
+        format!(
+            "unrecognised date '{}', try YYYY-MM-DD, today, tomorrow, a weekday, \
+             next week, eom or +3d",
+            input.trim()
+        )
+    })
+}
+
+/// Index of a weekday from Monday, given by its name or its first three
+/// letters
+pub(crate) fn weekday(word: &str) -> Option<u64> {
+    WEEKDAYS
+        .iter()
+        .position(|name| word.len() >= 3 && name.starts_with(word))
+        .map(|i| i as u64)
+}
+
+fn offset(today: Date, count: &str, unit: &str) -> Option<Date> {
+    let count: u64 = count.parse().ok()?;
+
+    match unit.trim_end_matches('s') {
+        "d" | "day" => today.add_days(count),
+        "w" | "week" => today.add_days(count.checked_mul(7)?),
+        "m" | "month" => today.add_months(count),
+        "y" | "year" => today.add_months(count.checked_mul(12)?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Saturday
+    fn today() -> Date {
+        "2026-10-17".parse().unwrap()
+    }
+
+    fn parsed(input: &str) -> String {
+        parse(input, today()).unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_iso_dates_and_simple_words() {
+        assert_eq!(parsed("2026-12-24"), "2026-12-24");
+        assert_eq!(parsed("today"), "2026-10-17");
+        assert_eq!(parsed("Tomorrow"), "2026-10-18");
+    }
+
+    #[test]
+    fn weekdays_are_always_ahead() {
+        assert_eq!(parsed("fri"), "2026-10-23");
+        assert_eq!(parsed("sunday"), "2026-10-18");
+        assert_eq!(parsed("sat"), "2026-10-24");
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert_eq!(parsed("next week"), "2026-10-19");
+        assert_eq!(parsed("next month"), "2026-11-01");
+        assert_eq!(parsed("eow"), "2026-10-18");
+        assert_eq!(parsed("eom"), "2026-10-31");
+        assert_eq!(parsed("eoy"), "2026-12-31");
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parsed("+3d"), "2026-10-20");
+        assert_eq!(parsed("+2w"), "2026-10-31");
+        assert_eq!(parsed("+1m"), "2026-11-17");
+        assert_eq!(parsed("in 1 year"), "2027-10-17");
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for input in [
+            "",
+            "+d",
+            "+3x",
+            "soon",
+            "2026-13-01",
+            "fr",
+            "+9999999999999999999y",
+            "+18446744073709551615d",
+            "in 8000 years",
+        ] {
+            assert!(parse(input, today()).is_err(), "{input}");
+        }
+    }
+}
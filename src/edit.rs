@@ -0,0 +1,657 @@
+//! The script `marc edit` opens in an editor, in the manner of `git rebase
+//! -i`: one line per todo, in the order `marc log` shows them, starting with
+//! a command saying what to do with it.
+//!
+//! A script can show only some of the todos of a list. The others are left
+//! as they are, and the edited todos take the places of the shown ones.
+
+use crate::status::Status;
+use crate::todo::{self, TodoItem, TodoList, TransitionError};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+const HELP: &str = "
+# Commands:
+#   pick, p <todo> = keep the todo
+#   reword, r <todo> <text> = keep the todo, with <text> as its description
+#   done, x <todo> = mark the todo done
+#   undone <todo> = reopen the todo
+#   tag, t <todo> <tag> = add a tag to the todo
+#   squash, s <todo> = merge the todo into the one above, joining descriptions
+#   fixup, f <todo> = like squash, but keep the description above
+#   drop, d <todo> = remove the todo
+#   add, a <text> = add a new todo
+#
+# <todo> is the number starting a line; the hash, status and tags after it
+# are only shown. Move lines to reorder the todos, and indent a todo under
+# another one to make it a subtask. Removing a line drops its todo, and
+# removing every line leaves the list as it is.
+# Lines starting with # are ignored
+";
+
+/// Comments `annotate` adds below the lines in error
+const ERROR_PREFIX: &str = "# error: ";
+
+/// A line of the script that can't be carried out
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// From 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The script for `tree`, numbering the todos from 1 and indenting subtasks
+/// under their parent. Each line shows the todo's hash, status and tags in
+/// columns before its description.
+pub(crate) fn script(tree: &[(usize, &TodoItem)]) -> String {
+    let mut script = String::new();
+    let number_width = tree.len().to_string().len();
+    let status_width = tree
+        .iter()
+        .map(|(_, item)| item.status.to_string().len())
+        .max()
+        .unwrap_or_default();
+
+    for (i, (depth, item)) in tree.iter().enumerate() {
+        let tags: String = item.tags.iter().map(|tag| format!("#{tag} ")).collect();
+        let _ = writeln!(
+            script,
+            "{}pick {:<number_width$} {} {:<status_width$} {tags}{}",
+            "  ".repeat(*depth),
+            i + 1,
+            item.hash,
+            item.status.to_string(),
+            item.desc
+        );
+    }
+
+    script.push_str(HELP);
+    script
+}
+
+/// The items of `list` once an edited script is carried out. `shown` are
+/// the items the script numbered; the items of the script take their
+/// places, in the order of its lines, each a subtask of the closest kept
+/// line above it that is indented less. Lines at the top keep a parent that
+/// was not shown. `done` and `undone` lines follow the rules of `marc done`
+/// and `marc reopen`, closing open subtasks along when `cascade` is set.
+/// Every line in error is reported, and nothing is changed then. A script
+/// without any command line gives nothing, as the edit was given up.
+pub(crate) fn parse(
+    content: &str,
+    list: &[TodoItem],
+    shown: &[TodoItem],
+    now: u64,
+    hash_length: usize,
+    cascade: bool,
+) -> Result<Option<Vec<TodoItem>>, Vec<ScriptError>> {
+    let mut script = Script {
+        shown,
+        now,
+        hash_length,
+        items: Vec::new(),
+        parents: Vec::new(),
+        merged: Vec::new(),
+        seen: HashMap::new(),
+        transitions: Vec::new(),
+    };
+    let mut errors = Vec::new();
+    let mut commands = 0;
+
+    for (number, line) in content.lines().enumerate() {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        commands += 1;
+        if let Err(message) = script.run(number + 1, indent, line) {
+            errors.push(ScriptError {
+                line: number + 1,
+                message,
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    if commands == 0 {
+        return Ok(None);
+    }
+
+    let mut pending = std::mem::take(&mut script.transitions);
+    let mut edited = TodoList::new();
+    edited.items = script.finish(list);
+
+    // A line can wait for a later one, like a todo done along with its
+    // blocker, so the transitions are tried again while some go through
+    loop {
+        let count = pending.len();
+        let mut failed = Vec::new();
+        for (line, hash, status) in pending {
+            let done_along = edited
+                .items
+                .iter()
+                .any(|item| item.hash == hash && item.status == status);
+            if done_along {
+                continue;
+            }
+            if let Err(e) = edited.set_status(&hash, status.clone(), cascade) {
+                failed.push((line, hash, status, e));
+            }
+        }
+
+        if failed.is_empty() {
+            return Ok(Some(edited.items));
+        }
+        if failed.len() == count {
+            return Err(failed
+                .into_iter()
+                .map(|(line, _, _, e)| ScriptError {
+                    line,
+                    message: transition_message(e),
+                })
+                .collect());
+        }
+        pending = failed
+            .into_iter()
+            .map(|(line, hash, status, _)| (line, hash, status))
+            .collect();
+    }
+}
+
+/// Why a `done` or `undone` line can't be carried out
+fn transition_message(error: TransitionError) -> String {
+    let message = match error {
+        TransitionError::Blocked(desc, blockers) => {
+            let blockers: Vec<String> = blockers
+                .iter()
+                .map(|(hash, desc)| format!("[{hash}] {desc}"))
+                .collect();
+            format!("'{desc}' waits for {}", blockers.join(", "))
+        }
+        TransitionError::OpenSubtasks(message) => {
+            format!("{message} (or set subtasks.complete to 'cascade' to close them along)")
+        }
+        TransitionError::NotFound(message) | TransitionError::Invalid(message) => message,
+        TransitionError::MultipleMatches(hash, _) => format!("several todos match '{hash}'"),
+    };
+    message
+        .strip_prefix("warning: ")
+        .map(str::to_string)
+        .unwrap_or(message)
+}
+
+/// `content` with each error written as a comment below its line, in place
+/// of the errors of a previous attempt
+pub(crate) fn annotate(content: &str, errors: &[ScriptError]) -> String {
+    let mut annotated = String::new();
+
+    for (number, line) in content.lines().enumerate() {
+        if line.trim_start().starts_with(ERROR_PREFIX) {
+            continue;
+        }
+        annotated.push_str(line);
+        annotated.push('\n');
+
+        for error in errors.iter().filter(|e| e.line == number + 1) {
+            annotated.push_str(&format!("{ERROR_PREFIX}{}\n", error.message));
+        }
+    }
+
+    annotated
+}
+
+/// What the lines read so far made of the list
+struct Script<'a> {
+    shown: &'a [TodoItem],
+    now: u64,
+    hash_length: usize,
+    items: Vec<TodoItem>,
+    /// Indentation and hash of the kept lines the next ones can be under
+    parents: Vec<(usize, String)>,
+    /// Hashes of the squashed items and of the items they were merged into
+    merged: Vec<(String, String)>,
+    /// The line each todo number was used on
+    seen: HashMap<usize, usize>,
+    /// Lines moving a todo to another state, with its hash and the state
+    transitions: Vec<(usize, String, Status)>,
+}
+
+impl Script<'_> {
+    fn run(&mut self, number: usize, indent: usize, line: &str) -> Result<(), String> {
+        let (command, rest) = split_word(line);
+
+        if matches!(command, "add" | "a") {
+            let (desc, tags) = todo::extract_tags(rest);
+            if desc.is_empty() {
+                return Err(format!("'{command}' needs the text of the new todo"));
+            }
+            let mut list = TodoList::new();
+            let mut item = list.add_item(desc, &tags, self.hash_length).clone();
+            item.created_at = self.now;
+            item.updated_at = self.now;
+            return self.keep(indent, item);
+        }
+
+        if !matches!(
+            command,
+            "pick"
+                | "p"
+                | "reword"
+                | "r"
+                | "done"
+                | "x"
+                | "undone"
+                | "tag"
+                | "t"
+                | "squash"
+                | "s"
+                | "fixup"
+                | "f"
+                | "drop"
+                | "d"
+        ) {
+            return Err(format!("unknown command '{command}'"));
+        }
+
+        let (index, text) = split_word(rest);
+        let index: usize = index
+            .parse()
+            .map_err(|_| format!("expected a todo number after '{command}'"))?;
+        let Some(item) = index.checked_sub(1).and_then(|i| self.shown.get(i)) else {
+            return Err(format!("there is no todo {index}"));
+        };
+        if let Some(line) = self.seen.insert(index, number) {
+            return Err(format!("todo {index} is already on line {line}"));
+        }
+        let text = skip_columns(item, text);
+
+        let mut item = item.clone();
+        match command {
+            "drop" | "d" => return Ok(()),
+            "squash" | "s" | "fixup" | "f" => {
+                return self.squash(indent, item, matches!(command, "squash" | "s"));
+            }
+            "reword" | "r" => {
+                let (desc, tags) = todo::extract_tags(text);
+                if desc.is_empty() {
+                    return Err(format!("'{command}' needs the new text of todo {index}"));
+                }
+                if item.desc != desc {
+                    item.desc = desc;
+                    item.updated_at = self.now;
+                }
+                add_tags(&mut item, &tags, self.now);
+            }
+            "tag" | "t" => {
+                let (name, _) = split_word(text);
+                let name = name.trim_start_matches(['+', '#']);
+                if name.is_empty() {
+                    return Err(format!("'{command}' needs the tag to add to todo {index}"));
+                }
+                todo::validate_tag(name)?;
+                add_tags(&mut item, &[name.to_string()], self.now);
+            }
+            "done" | "x" => return self.transition(number, indent, item, Status::Done),
+            "undone" => return self.transition(number, indent, item, Status::Open),
+            _ => {}
+        }
+
+        self.keep(indent, item)
+    }
+
+    /// Adds `item` to the list, under the closest line above indented less,
+    /// or under its parent when that one was not shown
+    fn keep(&mut self, indent: usize, mut item: TodoItem) -> Result<(), String> {
+        while self
+            .parents
+            .last()
+            .is_some_and(|(depth, _)| *depth >= indent)
+        {
+            self.parents.pop();
+        }
+
+        let parent = match self.parents.last() {
+            Some((_, hash)) => Some(hash.clone()),
+            None => item
+                .parent
+                .clone()
+                .filter(|parent| !self.shown.iter().any(|s| s.hash == *parent)),
+        };
+        if item.parent != parent {
+            item.parent = parent;
+            item.updated_at = self.now;
+        }
+
+        self.parents.push((indent, item.hash.clone()));
+        self.items.push(item);
+        Ok(())
+    }
+
+    /// Keeps `item`, to be moved to `status` once the whole script is read
+    fn transition(
+        &mut self,
+        number: usize,
+        indent: usize,
+        item: TodoItem,
+        status: Status,
+    ) -> Result<(), String> {
+        if item.status != status {
+            self.transitions.push((number, item.hash.clone(), status));
+        }
+        self.keep(indent, item)
+    }
+
+    /// Merges `item` into the item kept last, joining their descriptions
+    /// when `join` is set. The merged item takes its tags and dependencies,
+    /// and its due date and priority when it has none.
+    fn squash(&mut self, indent: usize, item: TodoItem, join: bool) -> Result<(), String> {
+        let now = self.now;
+        let Some(target) = self.items.last_mut() else {
+            return Err("there is no todo above to merge into".to_string());
+        };
+
+        if join {
+            target.desc = format!("{}; {}", target.desc, item.desc);
+        }
+        add_tags(target, &item.tags, now);
+        for hash in &item.depends_on {
+            if !target.depends_on.contains(hash) {
+                target.depends_on.push(hash.clone());
+            }
+        }
+        target.due = target.due.or(item.due);
+        target.priority = target.priority.or(item.priority);
+        target.updated_at = now;
+
+        // Lines indented under the squashed one go under the merged item
+        let hash = target.hash.clone();
+        while self
+            .parents
+            .last()
+            .is_some_and(|(depth, _)| *depth >= indent)
+        {
+            self.parents.pop();
+        }
+        self.parents.push((indent, hash.clone()));
+        self.merged.push((item.hash, hash));
+        Ok(())
+    }
+
+    /// `list` with the kept items in place of the shown ones. Subtasks and
+    /// dependencies of squashed items move to the items they were merged
+    /// into, subtasks of dropped items move up to the closest kept parent,
+    /// and dependencies on dropped items are forgotten.
+    fn finish(self, list: &[TodoItem]) -> Vec<TodoItem> {
+        let slots = list
+            .iter()
+            .filter(|item| self.shown.iter().any(|s| s.hash == item.hash))
+            .count();
+        let mut edited = self.items.into_iter();
+        let mut items: Vec<TodoItem> = Vec::new();
+        let mut filled = 0;
+
+        for item in list {
+            if !self.shown.iter().any(|s| s.hash == item.hash) {
+                items.push(item.clone());
+                continue;
+            }
+            filled += 1;
+            if filled == slots {
+                items.extend(edited.by_ref());
+            } else {
+                items.extend(edited.next());
+            }
+        }
+
+        let kept: Vec<String> = items.iter().map(|item| item.hash.clone()).collect();
+        let merged_into = |hash: &str| {
+            self.merged
+                .iter()
+                .find(|(from, _)| from == hash)
+                .map(|(_, into)| into.clone())
+        };
+        // A list edited by hand could have a cycle, hence the bound
+        let surviving_parent = |hash: &str| {
+            let mut hash = Some(hash.to_string());
+            for _ in 0..=list.len() {
+                let current = hash?;
+                if kept.contains(&current) {
+                    return Some(current);
+                }
+                if let Some(into) = merged_into(&current) {
+                    return Some(into);
+                }
+                hash = list
+                    .iter()
+                    .find(|item| item.hash == current)
+                    .and_then(|item| item.parent.clone());
+            }
+            None
+        };
+
+        for item in &mut items {
+            if let Some(parent) = item.parent.clone()
+                && !kept.contains(&parent)
+            {
+                item.parent = surviving_parent(&parent);
+                item.updated_at = self.now;
+            }
+
+            let mut depends_on: Vec<String> = Vec::new();
+            for hash in &item.depends_on {
+                let hash = merged_into(hash).unwrap_or_else(|| hash.clone());
+                if hash != item.hash && kept.contains(&hash) && !depends_on.contains(&hash) {
+                    depends_on.push(hash);
+                }
+            }
+
+            if depends_on != item.depends_on {
+                item.depends_on = depends_on;
+                item.updated_at = self.now;
+            }
+        }
+
+        items
+    }
+}
+
+/// `text` without the hash and status columns of `item`'s line, when they
+/// were left in
+fn skip_columns<'t>(item: &TodoItem, text: &'t str) -> &'t str {
+    let mut text = text;
+    for column in [item.hash.clone(), item.status.to_string()] {
+        let (word, rest) = split_word(text);
+        if word == column {
+            text = rest;
+        }
+    }
+    text
+}
+
+/// The first word of `text` and the rest of it
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn add_tags(item: &mut TodoItem, tags: &[String], now: u64) {
+    for tag in tags {
+        if !item.tags.contains(tag) {
+            item.tags.push(tag.clone());
+            item.updated_at = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `a`, `b`, `c` and `d`, with `d` waiting for `c`
+    fn items() -> Vec<TodoItem> {
+        let mut list = TodoList::new();
+        for desc in ["a", "b", "c", "d"] {
+            list.add_item(desc.to_string(), &[], 7);
+        }
+        let c = list.items()[2].hash.clone();
+        list.items.last_mut().unwrap().depends_on.push(c);
+        list.items
+    }
+
+    fn descs(items: &[TodoItem]) -> Vec<&str> {
+        items.iter().map(|item| item.desc.as_str()).collect()
+    }
+
+    #[test]
+    fn lines_reword_close_tag_and_reorder() {
+        let original = items();
+        let script = "pick 2\n\
+                      \x20 tag 4 +home d\n\
+                      reword 1 first +work\n\
+                      x 3 c\n\
+                      add new one";
+
+        let items = parse(script, &original, &original, 1, 7, false)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(descs(&items), ["b", "d", "first", "c", "new one"]);
+        assert_eq!(items[1].tags, ["home"]);
+        assert_eq!(items[1].parent.as_deref(), Some(original[1].hash()));
+        assert_eq!(items[2].tags, ["work"]);
+        assert_eq!(items[3].status, Status::Done);
+        assert_eq!(items[4].parent, None);
+    }
+
+    #[test]
+    fn scripts_without_commands_change_nothing() {
+        let original = items();
+        let full = script(&TodoList::tree(&original));
+        let emptied: Vec<&str> = full.lines().filter(|line| line.starts_with('#')).collect();
+        let emptied = emptied.join("\n");
+
+        assert_eq!(parse(&emptied, &original, &original, 1, 7, false), Ok(None));
+        assert_eq!(parse("\n  \n", &original, &original, 1, 7, false), Ok(None));
+    }
+
+    #[test]
+    fn done_lines_follow_the_rules_of_marc_done() {
+        let original = items();
+        let done = |script: &str, cascade: bool| {
+            parse(script, &original, &original, 1, 7, cascade)
+                .map(|items| {
+                    items
+                        .unwrap()
+                        .iter()
+                        .filter(|item| item.is_completed())
+                        .map(|item| item.desc.clone())
+                        .collect::<Vec<_>>()
+                })
+                .map_err(|errors| errors[0].to_string())
+        };
+
+        assert_eq!(
+            done("pick 1\npick 2\npick 3\nx 4", false),
+            Err(format!("line 4: 'd' waits for [{}] c", original[2].hash))
+        );
+        // d is done once c, on a later line, is
+        assert_eq!(
+            done("x 4\nx 3\npick 1\npick 2", false),
+            Ok(vec!["d".into(), "c".into()])
+        );
+
+        let subtask = "x 1\n  pick 2\npick 3\npick 4";
+        assert_eq!(
+            done(subtask, false),
+            Err("line 1: 'a' has an open subtask, complete it first \
+                 (or set subtasks.complete to 'cascade' to close them along)"
+                .to_string())
+        );
+        assert_eq!(done(subtask, true), Ok(vec!["a".into(), "b".into()]));
+    }
+
+    #[test]
+    fn squash_merges_into_the_todo_above() {
+        let original = items();
+        let script = "pick 2\ns 3\npick 4\nf 1";
+
+        let items = parse(script, &original, &original, 1, 7, false)
+            .unwrap()
+            .unwrap();
+
+        // d waited for c, which is now part of b
+        assert_eq!(descs(&items), ["b; c", "d"]);
+        assert_eq!(items[1].depends_on, [original[1].hash()]);
+        assert_eq!(items[1].updated_at, 1);
+    }
+
+    #[test]
+    fn errors_name_their_lines() {
+        let original = items();
+        let script = "pick 1\n# comment\npock 2\npick 9\npick 1\nsquash x\nundone 4";
+
+        let errors = parse(script, &original, &original, 1, 7, false).unwrap_err();
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            errors,
+            [
+                "line 3: unknown command 'pock'",
+                "line 4: there is no todo 9",
+                "line 5: todo 1 is already on line 1",
+                "line 6: expected a todo number after 'squash'",
+            ]
+        );
+    }
+
+    #[test]
+    fn scripts_of_some_todos_leave_the_others_alone() {
+        let mut original = items();
+        original[3].parent = Some(original[1].hash.clone());
+        let shown = [original[1].clone(), original[2].clone()];
+        let line = script(&TodoList::tree(&shown));
+        let line = line.lines().next().unwrap().replacen("pick", "reword", 1);
+
+        assert_eq!(line, format!("reword 1 {} open b", original[1].hash));
+
+        // c is dropped, and d, which was under b, waited for it
+        let script = format!("add e\n{line} +work");
+        let items = parse(&script, &original, &shown, 1, 7, false)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(descs(&items), ["a", "e", "b", "d"]);
+        assert_eq!(items[2].tags, ["work"]);
+        assert_eq!(items[3].parent.as_deref(), Some(original[1].hash()));
+        assert!(items[3].depends_on.is_empty());
+    }
+
+    #[test]
+    fn annotate_replaces_earlier_errors() {
+        let errors = [ScriptError {
+            line: 2,
+            message: "unknown command 'pock'".to_string(),
+        }];
+
+        let annotated = annotate("pick 1\npock 2\n# error: old\n", &errors);
+
+        assert_eq!(
+            annotated,
+            "pick 1\npock 2\n# error: unknown command 'pock'\n"
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! The append-only log of what happened to every item of a list.
+//!
+//! Events are derived by comparing the list before and after each command,
+//! so every command that saves through the journal is covered. Replaying the
+//! log from the start gives back the items of the list.
+
+use crate::date;
+use crate::recur::Recurrence;
+use crate::status::Status;
+use crate::todo::{Priority, TodoItem, TodoList};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Change {
+    Created {
+        item: TodoItem,
+    },
+    Edited {
+        desc: String,
+    },
+    Completed,
+    Reopened,
+    /// Moved to a state other than done or open
+    Transitioned {
+        status: Status,
+    },
+    Retagged {
+        #[serde(alias = "tag", deserialize_with = "crate::todo::deserialize_tags")]
+        tags: Vec<String>,
+    },
+    Reprioritized {
+        priority: Option<Priority>,
+    },
+    /// Made a subtask of another item, or a top-level one
+    Reparented {
+        parent: Option<String>,
+    },
+    /// Made to wait for these items
+    Rewired {
+        depends_on: Vec<String>,
+    },
+    /// Made to come back once done, or to stop coming back
+    Rescheduled {
+        recur: Option<Recurrence>,
+    },
+    /// Any other change, with the whole item as it became
+    Updated {
+        item: TodoItem,
+    },
+    Removed,
+    /// The list was put in this order
+    Reordered {
+        order: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    /// The item the change is about, none for list-wide changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+impl Event {
+    /// One line for `marc history`
+    pub fn describe(&self) -> String {
+        let what = match &self.change {
+            Change::Created { item } => {
+                format!("created '{}'{}", item.desc, hashtags(&item.tags))
+            }
+            Change::Edited { desc } => format!("edited to '{desc}'"),
+            Change::Completed => "completed".to_string(),
+            Change::Reopened => "reopened".to_string(),
+            Change::Transitioned {
+                status: status @ Status::Custom(_),
+            } => format!("marked {status}"),
+            Change::Transitioned { status } => status.to_string(),
+            Change::Retagged { tags } if tags.is_empty() => "untagged".to_string(),
+            Change::Retagged { tags } => format!("retagged{}", hashtags(tags)),
+            Change::Reprioritized {
+                priority: Some(priority),
+            } => format!("priority {priority}"),
+            Change::Reprioritized { priority: None } => "priority cleared".to_string(),
+            Change::Reparented {
+                parent: Some(parent),
+            } => format!("made a subtask of {parent}"),
+            Change::Reparented { parent: None } => "made a top-level todo".to_string(),
+            Change::Rewired { depends_on } if depends_on.is_empty() => {
+                "no longer waits for anything".to_string()
+            }
+            Change::Rewired { depends_on } => format!("waits for {}", depends_on.join(", ")),
+            Change::Rescheduled { recur: Some(recur) } => format!("recurs {recur}"),
+            Change::Rescheduled { recur: None } => "stopped recurring".to_string(),
+            Change::Updated { .. } => "updated".to_string(),
+            Change::Removed => "removed".to_string(),
+            Change::Reordered { .. } => "reordered the list".to_string(),
+        };
+
+        format!(
+            "{}  {:<7}  {what}",
+            date::format_datetime(self.at),
+            self.hash.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// ` #a #b`, or nothing without tags
+fn hashtags(tags: &[String]) -> String {
+    tags.iter().map(|tag| format!(" #{tag}")).collect()
+}
+
+/// The events that turn `before` into `after`
+pub fn diff(before: &TodoList, after: &TodoList, at: u64) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut event = |hash: Option<&str>, change, at| {
+        events.push(Event {
+            at,
+            hash: hash.map(str::to_string),
+            change,
+        })
+    };
+
+    for old in before.items() {
+        if !after.items().iter().any(|item| item.hash == old.hash) {
+            event(Some(&old.hash), Change::Removed, at);
+        }
+    }
+
+    for new in after.items() {
+        let Some(old) = before.items().iter().find(|item| item.hash == new.hash) else {
+            event(Some(&new.hash), Change::Created { item: new.clone() }, at);
+            continue;
+        };
+
+        // A change happens when the item says it was updated, which keeps
+        // the timestamps `rebuild` derives from the event equal to the
+        // item's own
+        let at = if new.updated_at >= old.updated_at {
+            new.updated_at
+        } else {
+            at
+        };
+        let mut changed = old.clone();
+
+        let mut changes = Vec::new();
+
+        if changed.desc != new.desc {
+            changes.push(Change::Edited {
+                desc: new.desc.clone(),
+            });
+        }
+
+        if changed.status != new.status {
+            changes.push(match &new.status {
+                Status::Done => Change::Completed,
+                Status::Open => Change::Reopened,
+                status => Change::Transitioned {
+                    status: status.clone(),
+                },
+            });
+        }
+
+        if changed.tags != new.tags {
+            changes.push(Change::Retagged {
+                tags: new.tags.clone(),
+            });
+        }
+
+        if changed.priority != new.priority {
+            changes.push(Change::Reprioritized {
+                priority: new.priority,
+            });
+        }
+
+        if changed.parent != new.parent {
+            changes.push(Change::Reparented {
+                parent: new.parent.clone(),
+            });
+        }
+
+        if changed.depends_on != new.depends_on {
+            changes.push(Change::Rewired {
+                depends_on: new.depends_on.clone(),
+            });
+        }
+
+        if changed.recur != new.recur {
+            changes.push(Change::Rescheduled {
+                recur: new.recur.clone(),
+            });
+        }
+
+        for change in &changes {
+            apply(&mut changed, change, at);
+        }
+
+        if changed != *new {
+            changes.push(Change::Updated { item: new.clone() });
+        }
+
+        for change in changes {
+            event(Some(&new.hash), change, at);
+        }
+    }
+
+    // Kept items stay in place and created ones go last unless the command
+    // said otherwise
+    let mut expected: Vec<&str> = before
+        .items()
+        .iter()
+        .map(|item| item.hash.as_str())
+        .filter(|hash| after.items().iter().any(|item| item.hash == *hash))
+        .collect();
+    expected.extend(
+        after
+            .items()
+            .iter()
+            .map(|item| item.hash.as_str())
+            .filter(|hash| !before.items().iter().any(|item| item.hash == *hash)),
+    );
+    let order: Vec<String> = after.items().iter().map(|item| item.hash.clone()).collect();
+
+    if expected != order {
+        event(None, Change::Reordered { order }, at);
+    }
+
+    events
+}
+
+/// The items as the log says they are now
+pub fn rebuild(events: &[Event]) -> Vec<TodoItem> {
+    let mut items: Vec<TodoItem> = Vec::new();
+
+    for event in events {
+        let position = event
+            .hash
+            .as_ref()
+            .and_then(|hash| items.iter().position(|item| item.hash == *hash));
+
+        match (&event.change, position) {
+            (Change::Created { item }, None) => items.push(item.clone()),
+            (Change::Removed, Some(i)) => {
+                items.remove(i);
+            }
+            (Change::Reordered { order }, _) => {
+                items.sort_by_key(|item| order.iter().position(|hash| *hash == item.hash));
+            }
+            (change, Some(i)) => apply(&mut items[i], change, event.at),
+            // Changes to items the log never saw created are left out, and
+            // show up in the consistency check
+            (_, None) => {}
+        }
+    }
+
+    items
+}
+
+/// Applies a change to an item, as it was made at `at`
+fn apply(item: &mut TodoItem, change: &Change, at: u64) {
+    match change {
+        Change::Created { item: new } | Change::Updated { item: new } => {
+            *item = new.clone();
+            return;
+        }
+        Change::Edited { desc } => item.desc = desc.clone(),
+        Change::Completed => {
+            item.status = Status::Done;
+            item.completed_at = Some(at);
+        }
+        Change::Reopened => {
+            item.status = Status::Open;
+            item.completed_at = None;
+        }
+        Change::Transitioned { status } => {
+            item.status = status.clone();
+            item.completed_at = status.is_closed().then_some(at);
+        }
+        Change::Retagged { tags } => item.tags = tags.clone(),
+        Change::Reprioritized { priority } => item.priority = *priority,
+        Change::Reparented { parent } => item.parent = parent.clone(),
+        Change::Rewired { depends_on } => item.depends_on = depends_on.clone(),
+        Change::Rescheduled { recur } => item.recur = recur.clone(),
+        Change::Removed | Change::Reordered { .. } => return,
+    }
+
+    item.updated_at = at;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo::sample_list;
+
+    #[test]
+    fn diff_names_each_change() {
+        let before = sample_list();
+        let mut after = before.clone();
+        after.mark_done(&before.items[0].hash, false).unwrap();
+        after.items[1].tags = vec!["errands".to_string()];
+        after.items[1].updated_at += 1;
+        after.items.remove(2);
+
+        let changes: Vec<Change> = diff(&before, &after, 0)
+            .into_iter()
+            .map(|e| e.change)
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                Change::Removed,
+                Change::Completed,
+                Change::Retagged {
+                    tags: vec!["errands".to_string()]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_events_written_before_tag_sets() {
+        let line = r#"{"at":1,"hash":"abc1234","event":"retagged","tag":"home"}"#;
+
+        let event: Event = serde_json::from_str(line).unwrap();
+
+        assert_eq!(
+            event.change,
+            Change::Retagged {
+                tags: vec!["home".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn rebuild_replays_the_log() {
+        let empty = TodoList::new();
+        let first = sample_list();
+        let mut second = first.clone();
+        second.items[1].desc = "buy oat milk".to_string();
+        second.items.swap(0, 2);
+        second.items.remove(1);
+
+        let mut events = diff(&empty, &first, 1);
+        events.extend(diff(&first, &second, 2));
+
+        assert_eq!(rebuild(&events), second.items);
+    }
+}
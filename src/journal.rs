@@ -0,0 +1,449 @@
+//! The undo journal kept alongside a list.
+//!
+//! Every operation stores only the todos it touched: each one as it was
+//! before and after, together with the hash of the todo above it. Undoing
+//! puts the `before` side back in place and leaves every other todo where it
+//! is. The todos are kept as plain item documents and go through the same
+//! schema migrations as the list when read.
+
+use crate::schema;
+use crate::todo::{Tags, TodoItem, TodoList};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub description: String,
+    pub(crate) before: Side,
+    pub(crate) after: Side,
+}
+
+/// The todos an operation touched on one side of it, and the declared tags
+/// when the operation changed them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct Side {
+    items: Vec<Placed>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<Tags>,
+}
+
+/// A todo and the hash of the todo above it, none at the top of the list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Placed {
+    previous: Option<String>,
+    item: TodoItem,
+}
+
+impl Entry {
+    /// Records the todos that differ between `before` and `after`
+    pub fn new(description: String, before: &TodoList, after: &TodoList) -> Entry {
+        let placed_before = placements(before);
+        let placed_after = placements(after);
+        let (tags_before, tags_after) = if before.tags == after.tags {
+            (None, None)
+        } else {
+            (Some(before.tags.clone()), Some(after.tags.clone()))
+        };
+
+        Entry {
+            description,
+            before: Side {
+                items: touched(&placed_before, &placed_after),
+                tags: tags_before,
+            },
+            after: Side {
+                items: touched(&placed_after, &placed_before),
+                tags: tags_after,
+            },
+        }
+    }
+}
+
+/// Every todo of `list` with the hash of the one above it
+fn placements(list: &TodoList) -> Vec<Placed> {
+    let mut previous = None;
+    list.items
+        .iter()
+        .map(|item| Placed {
+            previous: previous.replace(item.hash.clone()),
+            item: item.clone(),
+        })
+        .collect()
+}
+
+/// The todos of `side` that are not in `other` exactly as they are, in the
+/// same place
+fn touched(side: &[Placed], other: &[Placed]) -> Vec<Placed> {
+    let other: HashMap<&str, &Placed> = other
+        .iter()
+        .map(|placed| (placed.item.hash.as_str(), placed))
+        .collect();
+
+    side.iter()
+        .filter(|placed| other.get(placed.item.hash.as_str()) != Some(placed))
+        .cloned()
+        .collect()
+}
+
+/// Turns `list` from the `from` side of an operation into the `to` side.
+/// Gives nothing back when the todos the operation touched are not as
+/// `from` has them.
+fn apply(list: &TodoList, from: &Side, to: &Side) -> Option<TodoList> {
+    if from.tags.as_ref().is_some_and(|tags| *tags != list.tags) {
+        return None;
+    }
+
+    let placed = placements(list);
+    let current: HashMap<&str, &Placed> = placed
+        .iter()
+        .map(|placed| (placed.item.hash.as_str(), placed))
+        .collect();
+    let touched: HashSet<&str> = from
+        .items
+        .iter()
+        .chain(&to.items)
+        .map(|placed| placed.item.hash.as_str())
+        .collect();
+
+    let matches = from
+        .items
+        .iter()
+        .all(|placed| current.get(placed.item.hash.as_str()) == Some(&placed));
+    let added_elsewhere = to.items.iter().any(|placed| {
+        let hash = placed.item.hash.as_str();
+        current.contains_key(hash) && !from.items.iter().any(|p| p.item.hash == hash)
+    });
+    if !matches || added_elsewhere {
+        return None;
+    }
+
+    // Each todo is found by the one above it; untouched todos keep their place
+    let mut below: HashMap<Option<&str>, &TodoItem> = HashMap::new();
+    let kept = placed
+        .iter()
+        .filter(|placed| !touched.contains(placed.item.hash.as_str()));
+    for placed in kept.chain(&to.items) {
+        if below
+            .insert(placed.previous.as_deref(), &placed.item)
+            .is_some()
+        {
+            return None;
+        }
+    }
+
+    let mut items = Vec::with_capacity(below.len());
+    let mut previous = None;
+    while let Some(item) = below.remove(&previous) {
+        items.push(item.clone());
+        previous = Some(item.hash.as_str());
+    }
+    if !below.is_empty() {
+        return None;
+    }
+
+    Some(TodoList {
+        schema_version: list.schema_version,
+        items,
+        tags: to.tags.clone().unwrap_or_else(|| list.tags.clone()),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Journal {
+    /// Schema version of the todos kept in the entries
+    schema_version: u64,
+    /// Operations that can be undone, oldest first
+    pub(crate) undo: Vec<Entry>,
+    /// Undone operations that can be redone, most recently undone last
+    pub(crate) redo: Vec<Entry>,
+}
+
+impl Default for Journal {
+    fn default() -> Journal {
+        Journal {
+            schema_version: schema::CURRENT_VERSION,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl Journal {
+    /// Parses a journal written by this or an older marc. Journals without
+    /// a version keep whole lists in their entries, which are turned into
+    /// the todos that differ between them.
+    pub fn from_json(data: &str) -> Result<Journal, String> {
+        let mut doc: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+        let version = doc.get("schema_version").map(|v| {
+            v.as_u64()
+                .ok_or_else(|| format!("invalid schema_version {v}"))
+        });
+        let version = version.transpose()?;
+
+        for stack in ["undo", "redo"] {
+            let entries = doc
+                .get_mut(stack)
+                .and_then(Value::as_array_mut)
+                .into_iter()
+                .flatten();
+
+            for entry in entries {
+                match version {
+                    Some(version) => {
+                        for side in ["before", "after"] {
+                            if let Some(side) = entry.get_mut(side) {
+                                migrate_side(side, version)?;
+                            }
+                        }
+                    }
+                    None => *entry = from_snapshots(entry)?,
+                }
+            }
+        }
+
+        doc["schema_version"] = Value::from(schema::CURRENT_VERSION);
+        serde_json::from_value(doc).map_err(|e| e.to_string())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.undo
+    }
+
+    pub fn redo_entries(&self) -> &[Entry] {
+        &self.redo
+    }
+
+    /// Records a new operation, forgetting the oldest ones beyond `depth`.
+    /// Whatever was undone can no longer be redone.
+    pub fn record(&mut self, entry: Entry, depth: usize) {
+        self.redo.clear();
+        self.undo.push(entry);
+
+        if self.undo.len() > depth {
+            let excess = self.undo.len() - depth;
+            self.undo.drain(..excess);
+        }
+    }
+
+    /// Reverts the last operation on `list`.
+    ///
+    /// Refuses if the todos it touched are not as the operation left them,
+    /// which happens when they were changed without going through the journal.
+    pub fn undo(&mut self, list: &mut TodoList) -> Result<Option<String>, String> {
+        let Some(entry) = self.undo.pop() else {
+            return Ok(None);
+        };
+
+        let Some(reverted) = apply(list, &entry.after, &entry.before) else {
+            let description = entry.description.clone();
+            self.undo.push(entry);
+            return Err(format!(
+                "cannot undo ({description}): the list was changed in a way the journal did not record"
+            ));
+        };
+
+        *list = reverted;
+        let description = entry.description.clone();
+        self.redo.push(entry);
+        Ok(Some(description))
+    }
+
+    /// Applies the last undone operation to `list` again
+    pub fn redo(&mut self, list: &mut TodoList) -> Result<Option<String>, String> {
+        let Some(entry) = self.redo.pop() else {
+            return Ok(None);
+        };
+
+        let Some(replayed) = apply(list, &entry.before, &entry.after) else {
+            let description = entry.description.clone();
+            self.redo.push(entry);
+            return Err(format!(
+                "cannot redo ({description}): the list was changed in a way the journal did not record"
+            ));
+        };
+
+        *list = replayed;
+        let description = entry.description.clone();
+        self.undo.push(entry);
+        Ok(Some(description))
+    }
+}
+
+/// Migrates the todos and tags of one side of an entry, written at `version`
+fn migrate_side(side: &mut Value, version: u64) -> Result<(), String> {
+    let items: Vec<Value> = side
+        .get("items")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|placed| placed.get("item").cloned().unwrap_or_default())
+        .collect();
+    let tags = side.get("tags").filter(|tags| !tags.is_null()).cloned();
+
+    let mut doc = json!({ "schema_version": version, "items": items });
+    if let Some(tags) = &tags {
+        doc["tags"] = tags.clone();
+    }
+    schema::migrate(&mut doc)?;
+
+    let migrated = doc["items"].as_array_mut().map(std::mem::take);
+    let placed = side.get_mut("items").and_then(Value::as_array_mut);
+    for (placed, item) in placed
+        .into_iter()
+        .flatten()
+        .zip(migrated.into_iter().flatten())
+    {
+        placed["item"] = item;
+    }
+    if tags.is_some() {
+        side["tags"] = doc["tags"].take();
+    }
+    Ok(())
+}
+
+/// Turns an entry holding whole lists, as older journals have, into one
+/// holding the todos that differ between them
+fn from_snapshots(entry: &mut Value) -> Result<Value, String> {
+    let description = entry
+        .get("description")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let mut list = |snapshot: &str| -> Result<TodoList, String> {
+        let mut doc = entry.get_mut(snapshot).map(Value::take).unwrap_or_default();
+        schema::migrate(&mut doc)?;
+        serde_json::from_value(doc).map_err(|e| e.to_string())
+    };
+    let before = list("before")?;
+    let after = list("after")?;
+
+    serde_json::to_value(Entry::new(description, &before, &after)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo::sample_list;
+
+    fn descs(items: &[TodoItem]) -> Vec<&str> {
+        items.iter().map(|item| item.desc.as_str()).collect()
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_history() {
+        let empty = TodoList::new();
+        let sample = sample_list();
+        let mut journal = Journal::default();
+        journal.record(Entry::new("added 3 todos".to_string(), &empty, &sample), 10);
+
+        let mut list = sample.clone();
+        assert_eq!(
+            journal.undo(&mut list),
+            Ok(Some("added 3 todos".to_string()))
+        );
+        assert_eq!(list, empty);
+        assert_eq!(journal.undo(&mut list), Ok(None));
+
+        assert_eq!(
+            journal.redo(&mut list),
+            Ok(Some("added 3 todos".to_string()))
+        );
+        assert_eq!(list, sample);
+    }
+
+    #[test]
+    fn entries_keep_only_the_touched_todos() {
+        let before = sample_list();
+        let mut after = before.clone();
+        after.items.remove(1);
+        after.items[0].desc = "write more tests".to_string();
+
+        let entry = Entry::new("changed".to_string(), &before, &after);
+        let side =
+            |side: &Side| -> Vec<TodoItem> { side.items.iter().map(|p| p.item.clone()).collect() };
+        assert_eq!(
+            descs(&side(&entry.before)),
+            ["write tests", "buy milk", "ship it"]
+        );
+        assert_eq!(descs(&side(&entry.after)), ["write more tests", "ship it"]);
+
+        let mut journal = Journal::default();
+        journal.record(entry, 10);
+        let mut list = after.clone();
+        journal.undo(&mut list).unwrap();
+        assert_eq!(list, before);
+        journal.redo(&mut list).unwrap();
+        assert_eq!(list, after);
+    }
+
+    #[test]
+    fn undo_leaves_later_untouched_changes_alone() {
+        let before = sample_list();
+        let mut after = before.clone();
+        after.items[0].desc = "write more tests".to_string();
+        let mut journal = Journal::default();
+        journal.record(Entry::new("reworded".to_string(), &before, &after), 10);
+
+        let mut list = after.clone();
+        list.items[1].desc = "buy oat milk".to_string();
+        journal.undo(&mut list).unwrap();
+
+        assert_eq!(
+            descs(&list.items),
+            ["write tests", "buy oat milk", "ship it"]
+        );
+    }
+
+    #[test]
+    fn refuses_to_undo_over_unrecorded_changes() {
+        let mut journal = Journal::default();
+        let sample = sample_list();
+        journal.record(
+            Entry::new("added 3 todos".to_string(), &TodoList::new(), &sample),
+            10,
+        );
+
+        let mut list = sample.clone();
+        list.items[0].desc = "write more tests".to_string();
+        assert!(journal.undo(&mut list).is_err());
+        assert_eq!(journal.entries().len(), 1);
+    }
+
+    #[test]
+    fn reads_journals_of_whole_lists() {
+        let before = sample_list();
+        let mut after = before.clone();
+        after.add_item("call mum".to_string(), &[], 7);
+        let old = json!({
+            "undo": [{ "description": "added 'call mum'", "before": before, "after": after }],
+            "redo": [],
+        });
+
+        let mut journal = Journal::from_json(&old.to_string()).unwrap();
+        assert_eq!(journal.entries()[0].after.items.len(), 1);
+
+        let mut list = after.clone();
+        journal.undo(&mut list).unwrap();
+        assert_eq!(list, before);
+
+        let written = serde_json::to_string(&journal).unwrap();
+        assert_eq!(Journal::from_json(&written), Ok(journal));
+    }
+
+    #[test]
+    fn record_keeps_only_the_last_entries() {
+        let mut journal = Journal::default();
+        for i in 0..5 {
+            let empty = TodoList::new();
+            journal.record(Entry::new(i.to_string(), &empty, &empty), 3);
+        }
+
+        let kept: Vec<&str> = journal
+            .entries()
+            .iter()
+            .map(|e| e.description.as_str())
+            .collect();
+        assert_eq!(kept, ["2", "3", "4"]);
+    }
+}
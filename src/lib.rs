@@ -1,14 +1,30 @@
-use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
+use recur::Recurrence;
 use std::env::{self};
 use std::error::Error;
 use std::fs::{self};
-use std::hash::{Hash, Hasher};
 use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::Command;
-use tempfile::NamedTempFile;
+use std::str::FromStr;
+mod atomic;
 mod cli;
+mod config;
+mod date;
+mod edit;
+pub mod history;
+pub mod journal;
+pub mod lock;
+mod recur;
+mod schema;
+mod status;
+pub mod store;
+pub mod todo;
+mod urgency;
+
+pub use config::{Backend, Config};
+pub use status::Status;
+pub use store::{JsonStore, MemoryStore, Store};
+pub use todo::{Filter, Priority, TagInfo, TodoItem, TodoList, TransitionError};
 
 pub fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
     let cmd_line = cli::CommandLine::new(args)?;
@@ -19,12 +35,73 @@ pub fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
         return help();
     }
 
+    let overrides = cli::Arg::get_options(&cmd_line.args, &"config".to_string());
+    let mut config = Config::load_with(&overrides)?;
+
+    if let Some(file) = cli::Arg::get_option(&cmd_line.args, &"file".to_string()) {
+        config.file = Some(PathBuf::from(file));
+    }
+
+    config.list = cli::Arg::get_option(&cmd_line.args, &"list".to_string());
+    config.global = cli::Arg::get_flag(&cmd_line.args, &"global".to_string());
+
+    // These work on lists by name, and must not fail when the active list
+    // does not exist
+    match cmd_line.subcommand {
+        cli::Subcommand::Lists => return lists(&config),
+        cli::Subcommand::List => return list_cmd(cmd_line.args, &config),
+        cli::Subcommand::Init => return init(cmd_line.args, &config),
+        _ => {}
+    }
+
+    let store = config.open_store()?;
+    let store = store.as_ref();
+
     match cmd_line.subcommand {
-        cli::Subcommand::Add => add(cmd_line.args)?,
-        cli::Subcommand::Log => log(cmd_line.args)?,
-        cli::Subcommand::Done => done(cmd_line.args)?,
-        cli::Subcommand::Edit => edit()?,
-        cli::Subcommand::Remove => rm(cmd_line.args)?,
+        cli::Subcommand::Add => add(cmd_line.args, store, &config)?,
+        cli::Subcommand::Log => log(cmd_line.args, store, &config)?,
+        cli::Subcommand::Done => {
+            transition(cmd_line.args, store, &config, "done", Some(Status::Done))?
+        }
+        cli::Subcommand::Start => transition(
+            cmd_line.args,
+            store,
+            &config,
+            "start",
+            Some(Status::Started),
+        )?,
+        cli::Subcommand::Wait => {
+            transition(cmd_line.args, store, &config, "wait", Some(Status::Waiting))?
+        }
+        cli::Subcommand::Cancel => transition(
+            cmd_line.args,
+            store,
+            &config,
+            "cancel",
+            Some(Status::Cancelled),
+        )?,
+        cli::Subcommand::Reopen => {
+            transition(cmd_line.args, store, &config, "reopen", Some(Status::Open))?
+        }
+        cli::Subcommand::Status => transition(cmd_line.args, store, &config, "status", None)?,
+        cli::Subcommand::Edit => edit(cmd_line.args, store, &config)?,
+        cli::Subcommand::Remove => rm(cmd_line.args, store, &config)?,
+        cli::Subcommand::Undo => undo(cmd_line.args, store)?,
+        cli::Subcommand::Redo => redo(cmd_line.args, store)?,
+        cli::Subcommand::History => history(cmd_line.args, store)?,
+        cli::Subcommand::Next => next(store, &config)?,
+        cli::Subcommand::Modify => modify(cmd_line.args, store, &config)?,
+        cli::Subcommand::Tag => tag_cmd(cmd_line.args, store, &config)?,
+        cli::Subcommand::Block => block(cmd_line.args, store, &config, true)?,
+        cli::Subcommand::Unblock => block(cmd_line.args, store, &config, false)?,
+        cli::Subcommand::Graph => graph(store)?,
+        cli::Subcommand::Recur => recur(cmd_line.args, store, &config)?,
+        cli::Subcommand::Migrate => migrate(cmd_line.args, &config)?,
+        cli::Subcommand::Move => move_items(cmd_line.args, store, &config)?,
+        cli::Subcommand::Lists | cli::Subcommand::List | cli::Subcommand::Init => {
+            unreachable!()
+        }
+        cli::Subcommand::Config => config_cmd(cmd_line.args, &config)?,
         cli::Subcommand::Help => help()?,
         cli::Subcommand::Version => version(),
     };
@@ -32,282 +109,464 @@ pub fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub struct Config {}
-impl Config {
-    pub fn get_path() -> Result<PathBuf, Box<dyn Error>> {
-        let home_dir = env::var("HOME")
-            .or_else(|_| env::var("USERPROFILE"))
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("home directory not set: {e}"),
-                )
-            })?;
+/// Help command -- Displays all the commands, their usage and a short description
+fn help() -> Result<(), Box<dyn Error>> {
+    println!("read the source code");
+    Ok(())
+}
 
-        let mut app_data_dir = PathBuf::from(&home_dir);
-        app_data_dir.push("marc");
+/// Add command -- Adds entries to a list
+fn add(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let has_values = args
+        .iter()
+        .any(|entry| matches!(entry, cli::Arg::Value { .. }));
 
-        const DB_FILE_NAME: &str = "marc.json";
+    if !has_values {
+        return Err("'add' command requires at least one entry".into());
+    }
 
-        let path = app_data_dir.join(DB_FILE_NAME);
+    let _lock = store.lock("add")?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
 
-        if let Some(parent_dir) = path.parent() {
-            if !parent_dir.exists() {
-                fs::create_dir_all(parent_dir)?;
-            }
+    let tags = cli::Arg::get_options(&args, &"tag".to_string());
+    if tags.iter().any(|tag| tag.trim().is_empty()) {
+        return Err("add: tags cannot be empty".into());
+    }
+    for tag in &tags {
+        todo::validate_tag(tag).map_err(|e| format!("add: {e}"))?;
+    }
+    let now = date::now();
+    let due = match cli::Arg::get_option(&args, &"due".to_string()) {
+        Some(when) => Some(
+            date::parse(&when, date::Date::from_timestamp(now)).map_err(|e| format!("add: {e}"))?,
+        ),
+        None => None,
+    };
+    let priority = match cli::Arg::get_option(&args, &"priority".to_string()) {
+        Some(priority) => Some(Priority::from_str(&priority).map_err(|e| format!("add: {e}"))?),
+        None => None,
+    };
+    let recur = match cli::Arg::get_option(&args, &"recur".to_string()) {
+        Some(rule) => Some(Recurrence::from_str(&rule).map_err(|e| format!("add: {e}"))?),
+        None => None,
+    };
+    // Recurring todos need a due date to move forward from
+    let due = match &recur {
+        Some(recur) if due.is_none() => recur.first(date::Date::from_timestamp(now)),
+        _ => due,
+    };
+    let parent = match cli::Arg::get_option(&args, &"parent".to_string()) {
+        Some(prefix) => {
+            let index = resolve(&todo_list, &prefix, "add")?;
+            Some(todo_list.items()[index].hash().to_string())
         }
-
-        Ok(path)
+        None => None,
+    };
+    let mut depends_on = Vec::new();
+    for prefix in cli::Arg::get_options(&args, &"depends_on".to_string()) {
+        let index = resolve(&todo_list, &prefix, "add")?;
+        depends_on.push(todo_list.items()[index].hash().to_string());
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TodoItem {
-    hash: String,
-    desc: String,
-    is_completed: bool,
-    tag: Option<String>,
-}
+    let todos_to_add: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
 
-#[derive(Serialize, Deserialize, Debug)]
-struct TodoList {
-    items: Vec<TodoItem>,
-}
+    for todo in todos_to_add {
+        // Tags written in the text go after the ones given with --tag
+        let (todo, inline_tags) = todo::extract_tags(&todo);
+        if todo.is_empty() {
+            return Err("Todo items cannot be empty".into());
+        }
+        let mut item_tags = [tags.clone(), inline_tags].concat();
+        if item_tags.is_empty() {
+            item_tags.extend(config.default_tag.clone());
+        }
 
-impl TodoList {
-    fn new() -> Self {
-        TodoList { items: Vec::new() }
+        let item = todo_list.add_item(todo, &item_tags, config.hash_length);
+        item.due = due;
+        item.priority = priority;
+        item.parent = parent.clone();
+        item.depends_on = depends_on.clone();
+        item.recur = recur.clone();
+
+        let tag_display: String = item.tags().iter().map(|t| format!(" #{t}")).collect();
+        let due_display = item.due().map(|d| format!(" due {d}")).unwrap_or_default();
+        let recur_display = item
+            .recur()
+            .map(|r| format!(" recurs {r}"))
+            .unwrap_or_default();
+        let parent_display = item
+            .parent()
+            .map(|p| format!(" under [{p}]"))
+            .unwrap_or_default();
+        println!(
+            "Added: '{}'{tag_display}{due_display}{recur_display}{parent_display} [{}]",
+            item.desc(),
+            item.hash()
+        );
     }
 
-    fn load_from_file() -> Result<Self, Box<dyn Error>> {
-        let path: PathBuf = Config::get_path()?;
+    let added = &todo_list.items()[before.items().len()..];
+    let description = format!("added {}", describe(added));
+    commit(store, before, &todo_list, description, config)
+}
 
-        if !path.exists() {
-            return Ok(TodoList::new());
-        }
+/// List command -- Shows notes for a given list
+fn log(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let now = date::now();
+    let mut entries = select(&args, store, config, "log", now)?;
+    let context = store.context(&entries)?;
+    if config.sort_by_urgency {
+        urgency::sort(&mut entries, &config.urgency, now);
+    }
 
-        let data = fs::read_to_string(&path)
-            .map_err(|e| format!("error: failed to read todo file: {e}"))?;
+    println!(
+        "{}",
+        todo::paint(config.color, "2", &format!("On {}", store.location()))
+    );
+    TodoList::list_items(&entries, &context, config.color, now);
 
-        if data.trim().is_empty() {
-            return Ok(TodoList::new());
-        }
+    Ok(())
+}
 
-        match serde_json::from_str(&data) {
-            Ok(list) => Ok(list),
-            Err(e) => Err(format!(
-                "error: failed to parse todo file ({}). Error: {}",
-                path.display(),
-                e
-            )
-            .into()),
-        }
+/// The items of `todo_list` matching the filters of `log` and `edit`, in
+/// list order
+fn select(
+    args: &[cli::Arg],
+    store: &dyn Store,
+    config: &Config,
+    command: &str,
+    now: u64,
+) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+    let tags: Vec<String> = cli::Arg::get_options(args, &"tag".to_string());
+    let any_tag: bool = cli::Arg::get_flag(args, &"any".to_string());
+    let only_done: bool = cli::Arg::get_flag(args, &"done".to_string());
+    let only_undone: bool = cli::Arg::get_flag(args, &"undone".to_string());
+    let overdue: bool = cli::Arg::get_flag(args, &"overdue".to_string());
+    let only_blocked: bool = cli::Arg::get_flag(args, &"blocked".to_string());
+    let ready: bool = cli::Arg::get_flag(args, &"ready".to_string());
+    let mut statuses = Vec::new();
+    for status in cli::Arg::get_options(args, &"status".to_string()) {
+        statuses.push(
+            Status::parse(&status, &config.custom_statuses)
+                .map_err(|e| format!("{command}: {e}"))?,
+        );
+    }
+    if only_done && !statuses.contains(&Status::Done) {
+        statuses.push(Status::Done);
     }
 
-    fn add_item(&mut self, desc: String, tag: &Option<String>) {
-        let id = Self::generate_short_hash(&desc, tag);
-        let new_item = TodoItem {
-            hash: id.clone(),
-            desc: desc.clone(),
-            is_completed: false,
-            tag: Some(tag.clone().unwrap_or("default".to_string())),
-        };
-        self.items.push(new_item);
-
-        let tag_display = self
-            .items
-            .last()
-            .unwrap()
-            .tag
-            .as_ref()
-            .map(|t| format!(" #{t}"))
-            .unwrap_or_default();
-        println!("Added: '{desc}'{tag_display} [{id}]");
+    if only_blocked && ready {
+        return Err(format!("{command}: --blocked and --ready cannot be used together").into());
     }
 
-    fn rm_item(&mut self, hash: &str) -> Option<TodoItem> {
-        let matching_items: Vec<usize> = self
-            .items
-            .iter()
-            .enumerate()
-            .filter(|(_, item)| item.hash.starts_with(hash))
-            .map(|(i, _)| i)
-            .collect();
+    let today = date::Date::from_timestamp(now);
+    let due_before = match cli::Arg::get_option(args, &"due_before".to_string()) {
+        Some(when) => Some(date::parse(&when, today).map_err(|e| format!("{command}: {e}"))?),
+        None => None,
+    };
 
-        match matching_items.len() {
-            0 => None,
-            1 => {
-                let index = matching_items[0];
+    // Overdue means due before today and not closed yet
+    let filter = Filter {
+        tags,
+        any_tag,
+        closed: (only_undone || overdue || only_blocked || ready).then_some(false),
+        statuses,
+        due_before: match (overdue, due_before) {
+            (true, Some(day)) => Some(day.min(today)),
+            (true, None) => Some(today),
+            (false, day) => day,
+        },
+    };
 
-                Some(self.items.remove(index))
-            }
-            _ => None,
-        }
+    // Whether an item is blocked depends on other items, so it is not part
+    // of the filter
+    let mut entries = store.query(&filter)?;
+    if only_blocked || ready {
+        let context = store.context(&entries)?;
+        entries.retain(|item| context.is_blocked(item) == only_blocked);
     }
 
-    fn save_to_file(&self) -> Result<(), Box<dyn Error>> {
-        let path = Config::get_path()?;
+    Ok(entries)
+}
+
+/// Next command -- Shows the most urgent open todo that is neither blocked
+/// nor waiting
+fn next(store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let now = date::now();
+    let filter = Filter {
+        closed: Some(false),
+        ..Filter::default()
+    };
+
+    let mut entries = store.query(&filter)?;
+    let context = store.context(&entries)?;
+    entries.retain(|item| !context.is_blocked(item) && *item.status() != Status::Waiting);
+    urgency::sort(&mut entries, &config.urgency, now);
 
-        let serialized = serde_json::to_string_pretty(&self)
-            .map_err(|e| format!("error: failed to serialize todo data: {e}"))?;
-        fs::write(&path, serialized).map_err(|e| {
-            format!(
-                "error: failed to write to todo file ({}): {}",
-                path.display(),
-                e
-            )
-        })?;
-        Ok(())
+    match entries.first() {
+        Some(item) => context.print_item(item, 0, config.color, now),
+        None => println!("Nothing to do"),
     }
 
-    fn list_items(&self, tag: Option<String>, only_done: bool, only_undone: bool) {
-        let mut entries = match tag {
-            Some(_) => self
-                .items
-                .iter()
-                .filter(|p| p.tag == tag)
-                .cloned()
-                .collect(),
-            None => self.items.clone(),
-        };
+    Ok(())
+}
 
-        if only_done {
-            entries.retain(|e| e.is_completed);
-        } else if only_undone {
-            entries.retain(|e| !e.is_completed);
-        }
+/// Modify command -- Changes the description, tags, due date or priority of
+/// todos picked by hash prefixes or filters, and tells what changed on each
+fn modify(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let now = date::now();
+    let today = date::Date::from_timestamp(now);
 
-        if entries.is_empty() {
-            println!("No entries");
-            return;
-        }
+    let tags = cli::Arg::get_options(&args, &"tag".to_string());
+    if tags.iter().any(|tag| tag.trim().is_empty()) {
+        return Err("modify: tags cannot be empty".into());
+    }
+    for tag in &tags {
+        todo::validate_tag(tag).map_err(|e| format!("modify: {e}"))?;
+    }
+    // `Some(None)` clears the field
+    let due = match cli::Arg::get_option(&args, &"due".to_string()) {
+        Some(when) if when.eq_ignore_ascii_case("none") => Some(None),
+        Some(when) => Some(Some(
+            date::parse(&when, today).map_err(|e| format!("modify: {e}"))?,
+        )),
+        None => None,
+    };
+    let priority = match cli::Arg::get_option(&args, &"priority".to_string()) {
+        Some(p) if p.eq_ignore_ascii_case("none") => Some(None),
+        Some(p) => Some(Some(
+            Priority::from_str(&p).map_err(|e| format!("modify: {e}"))?,
+        )),
+        None => None,
+    };
+    let with_tags = cli::Arg::get_options(&args, &"with_tag".to_string());
+    let mut with_statuses = Vec::new();
+    for name in cli::Arg::get_options(&args, &"with_status".to_string()) {
+        with_statuses.push(
+            Status::parse(&name, &config.custom_statuses).map_err(|e| format!("modify: {e}"))?,
+        );
+    }
+    let batch = !with_tags.is_empty() || !with_statuses.is_empty();
 
-        println!("\x1b[1;31m total {}\x1b[0m", entries.len());
+    let values: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
 
-        for item in entries.iter() {
-            let (desc, status) = if item.is_completed {
-                (item.desc.clone(), 1)
-            } else {
-                (item.desc.clone(), 0)
-            };
+    let _lock = store.lock("modify")?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
+
+    // Hash prefixes come before the words of the new description, up to a
+    // `--` if a word could be taken for one, and `+tag` and `-tag` can go
+    // anywhere
+    let mut targets: Vec<usize> = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    let mut added: Vec<String> = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    let mut hashes_ended = false;
+    for value in &values {
+        if value == "--" && !hashes_ended {
+            hashes_ended = true;
+            continue;
+        }
 
-            println!(
-                "{} {} {} {}",
-                status,
-                item.hash,
-                item.tag
-                    .as_ref()
-                    .map_or(String::new(), |tag| format!("\x1b[36m#{tag}\x1b[0m")),
-                desc,
-            );
+        let is_hash = words.is_empty()
+            && !hashes_ended
+            && value.len() >= todo::MIN_HASH_LENGTH
+            && value.chars().all(|c| c.is_ascii_hexdigit())
+            && todo_list
+                .items()
+                .iter()
+                .any(|item| item.hash().starts_with(value));
+
+        if is_hash {
+            let index = resolve(&todo_list, value, "modify")?;
+            if !targets.contains(&index) {
+                targets.push(index);
+            }
+        } else if let Some(tag) = value.strip_prefix('+')
+            && todo::validate_tag(tag).is_ok()
+        {
+            added.push(tag.to_string());
+        } else if let Some(tag) = value.strip_prefix('-')
+            && todo::validate_tag(tag).is_ok()
+        {
+            removed.push(tag.to_string());
+        } else {
+            words.push(value);
         }
     }
 
-    fn generate_short_hash(desc: &str, tag: &Option<String>) -> String {
-        let mut hasher = DefaultHasher::new();
-        desc.hash(&mut hasher);
-        if let Some(tag_value) = tag {
-            tag_value.hash(&mut hasher);
+    let desc = match words.as_slice() {
+        [] => None,
+        words => {
+            let (desc, inline_tags) = todo::extract_tags(&words.join(" "));
+            if desc.is_empty() {
+                return Err("modify: descriptions cannot be empty".into());
+            }
+            added.extend(inline_tags);
+            Some(desc)
         }
-        // Use current timestamp to ensure uniqueness
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-            .hash(&mut hasher);
+    };
 
-        let hash = hasher.finish();
-        format!("{hash:x}")[..7].to_string()
+    if desc.is_none()
+        && tags.is_empty()
+        && added.is_empty()
+        && removed.is_empty()
+        && due.is_none()
+        && priority.is_none()
+    {
+        return Err(
+            "modify: nothing to change, give a description, --tag, +tag, -tag, \
+                    --due or --priority"
+                .into(),
+        );
     }
 
-    fn mark_done(&mut self, hash: &str) -> Result<usize, MarkDoneError> {
-        let matching_items: Vec<usize> = self
-            .items
-            .iter()
-            .enumerate()
-            .filter(|(_, item)| item.hash.starts_with(hash))
-            .map(|(i, _)| i)
-            .collect();
-
-        match matching_items.len() {
-            0 => Err(MarkDoneError::NotFound(format!(
-                "warning: no todo found with hash' {hash}'",
-            ))),
-            1 => {
-                let index = matching_items[0];
-                if self.items[index].is_completed {
-                    Err(MarkDoneError::AlreadyCompleted(
-                        "warning: todo is already completed".to_string(),
-                    ))
-                } else {
-                    self.items[index].is_completed = true;
-                    Ok(1)
-                }
-            }
-            _ => {
-                let matches: Vec<(String, String)> = matching_items
-                    .iter()
-                    .map(|&i| (self.items[i].hash.clone(), self.items[i].desc.clone()))
-                    .collect();
-                Err(MarkDoneError::MultipleMatches(hash.to_string(), matches))
+    if batch {
+        let filter = Filter {
+            tags: with_tags,
+            statuses: with_statuses,
+            ..Filter::default()
+        };
+        let selected = todo_list.filtered(&filter);
+        if selected.is_empty() {
+            return Err("modify: no todos match the filters".into());
+        }
+        confirm_batch(
+            "modify",
+            &selected,
+            cli::Arg::get_flag(&args, &"yes".to_string()),
+            config,
+        )?;
+
+        for item in &selected {
+            let index = resolve(&todo_list, item.hash(), "modify")?;
+            if !targets.contains(&index) {
+                targets.push(index);
             }
         }
     }
-}
-
-/// Help command -- Displays all the commands, their usage and a short description
-fn help() -> Result<(), Box<dyn Error>> {
-    println!("read the source code");
-    Ok(())
-}
-
-/// Add command -- Adds entries to a list
-fn add(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
-    let has_values = args
-        .iter()
-        .any(|entry| matches!(entry, cli::Arg::Value { .. }));
 
-    if !has_values {
-        return Err("'add' command requires at least one entry".into());
+    match targets.len() {
+        0 => return Err("modify: should at least specify one hash".into()),
+        1 => {}
+        _ if desc.is_some() => {
+            return Err("modify: a new description can only be given to one todo".into());
+        }
+        _ => {}
     }
 
-    let mut todo_list = TodoList::load_from_file()?;
+    let mut changed = Vec::new();
+    for index in targets {
+        let item = &mut todo_list.items[index];
+        let old = item.clone();
 
-    let tag = cli::Arg::get_option(&args, &"tag".to_string());
+        if let Some(desc) = &desc {
+            item.desc = desc.clone();
+        }
+        if !tags.is_empty() {
+            item.tags.clear();
+        }
+        for tag in tags.iter().chain(&added) {
+            if !item.tags.contains(tag) {
+                item.tags.push(tag.clone());
+            }
+        }
+        item.tags.retain(|tag| !removed.contains(tag));
+        if let Some(due) = due {
+            item.due = due;
+        }
+        if let Some(priority) = priority {
+            item.priority = priority;
+        }
 
-    let todos_to_add: Vec<String> = args
-        .iter()
-        .filter_map(|arg| match arg {
-            cli::Arg::Value(value) => Some(value.clone()),
-            _ => None,
-        })
-        .collect();
+        let changes = field_changes(&old, item);
+        if changes.is_empty() {
+            println!("Unchanged: [{}] {}", item.hash(), item.desc());
+            continue;
+        }
 
-    for todo in todos_to_add {
-        if todo.trim().is_empty() {
-            return Err("Todo items cannot be empty".into());
+        item.updated_at = now;
+        println!("Modified: [{}] {}", item.hash(), item.desc());
+        for change in changes {
+            println!("  {change}");
         }
-        todo_list.add_item(todo.clone(), &tag);
+        changed.push(item.clone());
     }
 
-    todo_list.save_to_file()?;
-    Ok(())
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let description = format!("modified {}", describe(&changed));
+    commit(store, before, &todo_list, description, config)
 }
 
-/// List command -- Shows notes for a given list
-fn log(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
-    let todo_list = TodoList::load_from_file()?;
+/// What `modify` changed on an item, one line per field
+fn field_changes(old: &TodoItem, new: &TodoItem) -> Vec<String> {
+    let tags = |item: &TodoItem| match item.tags() {
+        [] => "none".to_string(),
+        tags => tags
+            .iter()
+            .map(|tag| format!("#{tag}"))
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    let due = |item: &TodoItem| item.due().map_or("none".to_string(), |due| due.to_string());
+    let priority = |item: &TodoItem| {
+        item.priority()
+            .map_or("none".to_string(), |priority| priority.to_string())
+    };
 
-    let tag: Option<String> = cli::Arg::get_option(&args, &"tag".to_string());
-    let only_done: bool = cli::Arg::get_flag(&args, &"done".to_string());
-    let only_undone: bool = cli::Arg::get_flag(&args, &"undone".to_string());
+    let mut changes = Vec::new();
+    if old.desc() != new.desc() {
+        changes.push(format!("description: '{}' -> '{}'", old.desc(), new.desc()));
+    }
+    if old.tags() != new.tags() {
+        changes.push(format!("tags: {} -> {}", tags(old), tags(new)));
+    }
+    if old.due() != new.due() {
+        changes.push(format!("due: {} -> {}", due(old), due(new)));
+    }
+    if old.priority() != new.priority() {
+        changes.push(format!("priority: {} -> {}", priority(old), priority(new)));
+    }
+    changes
+}
 
-    todo_list.list_items(tag, only_done, only_undone);
+/// The index of the one item whose hash starts with `prefix`
+fn resolve(todo_list: &TodoList, prefix: &str, command: &str) -> Result<usize, Box<dyn Error>> {
+    let matches: Vec<usize> = todo_list
+        .items()
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| !prefix.is_empty() && item.hash().starts_with(prefix))
+        .map(|(i, _)| i)
+        .collect();
 
-    Ok(())
+    match matches.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(format!("{command}: no todo found with hash '{prefix}'").into()),
+        _ => Err(format!(
+            "{command}: multiple todos found matching '{prefix}', please be more specific"
+        )
+        .into()),
+    }
 }
 
-fn rm(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
+fn rm(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
     let only_completed: bool = cli::Arg::get_flag(&args, &"done".to_string());
 
     let hashes: Vec<String> = args
@@ -322,16 +581,28 @@ fn rm(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
         return Err("remove: should at least specify one hash".into());
     }
 
-    let mut todo_list = TodoList::load_from_file()?;
+    let _lock = store.lock("rm")?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
 
     if todo_list.items.is_empty() {
         return Err("No todos to remove".into());
     }
 
     if only_completed {
-        todo_list.items.retain(|t| !t.is_completed);
-        todo_list.save_to_file()?;
-        return Ok(());
+        let completed: Vec<String> = todo_list
+            .items()
+            .iter()
+            .filter(|item| item.is_completed())
+            .map(|item| item.hash().to_string())
+            .collect();
+        todo_list.remove_items(&completed, config.cascade_removal);
+        let removed = before.items().len() - todo_list.items().len();
+        let description = match removed {
+            1 => "removed 1 completed todo".to_string(),
+            _ => format!("removed {removed} completed todos"),
+        };
+        return commit(store, before, &todo_list, description, config);
     }
 
     for prefix in hashes {
@@ -339,164 +610,1369 @@ fn rm(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
             continue;
         };
 
-        todo_list.rm_item(&prefix);
+        todo_list.rm_item(&prefix, config.cascade_removal);
     }
 
-    todo_list.save_to_file()?;
-    Ok(())
+    let removed = removed_items(&before, &todo_list);
+    let description = format!("removed {}", describe(&removed));
+    commit(store, before, &todo_list, description, config)
 }
 
-/// Interactive edit command -- Opens editor to pick/drop todos
-fn edit() -> Result<(), Box<dyn Error>> {
-    let mut todo_list = TodoList::load_from_file()?;
-
-    if todo_list.items.is_empty() {
+/// Interactive edit command -- Opens an editor on a script of the todos to
+/// pick, reword, close, tag, squash, drop or reorder them, and add new ones.
+/// The filters of `log` limit the script to the matching todos, leaving the
+/// others untouched. A script with errors is opened again with the errors
+/// below their lines, until it is fixed or saved unchanged.
+///
+/// The lock is not held while the editor is open, so other commands keep
+/// working; the list is reloaded under the lock afterwards and the edits are
+/// refused if it changed in the meantime, keeping the script so the user can
+/// copy it into the next session.
+fn edit(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let snapshot = store.load()?;
+
+    if snapshot.items.is_empty() {
         return Err("No todos to edit! Add some todos first with 'marc add <todo>'".into());
     }
 
-    let mut temp_file = NamedTempFile::new()?;
-
-    for (i, item) in todo_list.items.iter().enumerate() {
-        writeln!(temp_file, "pick {} {}", i + 1, item.desc)?;
+    let entries = select(&args, store, config, "edit", date::now())?;
+    if entries.is_empty() {
+        return Err("edit: no todos match the filters".into());
     }
 
-    writeln!(temp_file, "\n# Interactive todo editing")?;
-    writeln!(temp_file, "# Commands:")?;
-    writeln!(temp_file, "#   pick, p <todo> = keep the todo")?;
-    writeln!(temp_file, "#   drop, d <todo> = remove the todo")?;
-    writeln!(temp_file, "# Lines starting with # are ignored")?;
+    let temp_file = tempfile::Builder::new().prefix("marc-edit-").tempfile()?;
 
-    temp_file.flush()?;
+    // Todos are numbered in the order they are shown, subtasks indented
+    // under their parent
+    let tree = TodoList::tree(&entries);
+    let ordered: Vec<TodoItem> = tree.iter().map(|(_, item)| (*item).clone()).collect();
+    let mut content = edit::script(&tree);
 
-    let editor = env::var("EDITOR").unwrap_or_else(|_| "vim".to_string());
+    let editor = config
+        .editor
+        .clone()
+        .or_else(|| env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vim".to_string());
 
-    let status = Command::new(&editor).arg(temp_file.path()).status()?;
+    let new_items = loop {
+        fs::write(temp_file.path(), &content)?;
 
-    if !status.success() {
-        return Err(format!("Editor '{editor}' exited with an error. Make sure your EDITOR environment variable is set correctly.").into());
-    }
+        let status = Command::new(&editor).arg(temp_file.path()).status()?;
+
+        if !status.success() {
+            return Err(format!("Editor '{editor}' exited with an error. Make sure edit.editor or your EDITOR environment variable is set correctly.").into());
+        }
+
+        let edited_content = fs::read_to_string(temp_file.path())?;
+
+        match edit::parse(
+            &edited_content,
+            &snapshot.items,
+            &ordered,
+            date::now(),
+            config.hash_length,
+            config.cascade_completion,
+        ) {
+            Ok(Some(items)) => break items,
+            Ok(None) => return Err("edit: the script is empty, nothing was changed".into()),
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("edit: {error}");
+                }
+                if edited_content == content {
+                    return Err("edit: the script still has errors, nothing was changed".into());
+                }
+                eprintln!("edit: fix the script, or save it unchanged to give up");
+                content = edit::annotate(&edited_content, &errors);
+            }
+        }
+    };
 
-    let edited_content = fs::read_to_string(temp_file.path())?;
+    let _lock = store.lock("edit")?;
+    let mut todo_list = store.load()?;
+
+    if todo_list != snapshot {
+        let (_, kept) = temp_file.keep()?;
+        return Err(format!(
+            "the todo file changed while the editor was open; refusing to overwrite it. \
+             Your script was kept in {}, run 'marc edit' again to apply your changes",
+            kept.display()
+        )
+        .into());
+    }
 
-    let new_items = parse_edit_commands(&edited_content, &todo_list.items)?;
     todo_list.items = new_items;
 
-    todo_list.save_to_file()?;
+    let dropped = removed_items(&snapshot, &todo_list);
+    let description = if dropped.is_empty() {
+        "edited the list".to_string()
+    } else {
+        format!("edited the list, dropping {}", describe(&dropped))
+    };
+    commit(store, snapshot, &todo_list, description, config)?;
 
     println!("Todo list updated!");
 
     Ok(())
 }
 
-/// Parse edit commands and return new list of todos
-fn parse_edit_commands(
-    content: &str,
-    original_items: &[TodoItem],
-) -> Result<Vec<TodoItem>, Box<dyn Error>> {
-    let mut new_items = Vec::new();
+/// Saves `after` and records the change from `before` in the undo journal
+fn commit(
+    store: &dyn Store,
+    before: TodoList,
+    after: &TodoList,
+    description: String,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    if before == *after {
+        return Ok(());
+    }
 
-    for line in content.lines() {
-        let line = line.trim();
+    store.save(after)?;
+    record_history(store, &before, after)?;
 
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+    let mut journal = store.load_journal()?;
+    journal.record(
+        journal::Entry::new(description, &before, after),
+        config.undo_depth,
+    );
+    store.save_journal(&journal)
+}
 
-        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+/// Appends the changes from `before` to `after` to the list's history. A
+/// list without history first gets its items recorded as created, so that
+/// replaying the history gives the list back.
+fn record_history(
+    store: &dyn Store,
+    before: &TodoList,
+    after: &TodoList,
+) -> Result<(), Box<dyn Error>> {
+    let at = date::now();
+    let mut events = Vec::new();
+
+    if !store.has_history()? {
+        events = history::diff(&TodoList::new(), before, at);
+    }
+    events.extend(history::diff(before, after, at));
 
-        if parts.len() < 2 {
-            continue; // Skip malformed lines
-        }
+    if events.is_empty() {
+        return Ok(());
+    }
+    store.append_history(&events)
+}
 
-        let command = parts[0];
-        let index_str = parts[1];
+/// Items of `before` that are no longer in `after`
+fn removed_items(before: &TodoList, after: &TodoList) -> Vec<TodoItem> {
+    before
+        .items()
+        .iter()
+        .filter(|item| !after.items().iter().any(|a| a.hash == item.hash))
+        .cloned()
+        .collect()
+}
 
-        let index: usize = match index_str.parse::<usize>() {
-            Ok(i) if i > 0 && i <= original_items.len() => i - 1, // Convert to 0-based
-            _ => continue,                                        // Skip invalid indices
-        };
+/// Names a single todo by its description and counts several, for journal
+/// entries
+fn describe(items: &[TodoItem]) -> String {
+    match items {
+        [item] => format!("'{}'", item.desc()),
+        _ => count_todos(items.len()),
+    }
+}
 
-        match command {
-            "pick" | "p" => {
-                if let Some(item) = original_items.get(index) {
-                    new_items.push(item.clone());
-                }
-            }
-            "drop" | "d" => {}
-            _ => {
-                if let Some(item) = original_items.get(index) {
-                    new_items.push(item.clone());
-                }
+fn count_todos(count: usize) -> String {
+    match count {
+        1 => "1 todo".to_string(),
+        _ => format!("{count} todos"),
+    }
+}
+
+/// Undo command -- Reverts the last operations, or lists them with --list
+fn undo(args: Vec<cli::Arg>, store: &dyn Store) -> Result<(), Box<dyn Error>> {
+    if cli::Arg::get_flag(&args, &"preview".to_string()) {
+        let journal = store.load_journal()?;
+
+        if journal.entries().is_empty() && journal.redo_entries().is_empty() {
+            println!("Nothing to undo");
+            return Ok(());
+        }
+
+        for (i, entry) in journal.entries().iter().rev().enumerate() {
+            println!("{:>3}  {}", i + 1, entry.description);
+        }
+
+        if !journal.redo_entries().is_empty() {
+            println!("Can be redone:");
+            for (i, entry) in journal.redo_entries().iter().rev().enumerate() {
+                println!("{:>3}  {}", i + 1, entry.description);
             }
         }
+
+        return Ok(());
     }
 
-    Ok(new_items)
+    replay(args, store, "undo", journal::Journal::undo)
 }
 
-#[derive(Debug)]
-enum MarkDoneError {
-    NotFound(String),
-    AlreadyCompleted(String),
-    MultipleMatches(String, Vec<(String, String)>), // prefix, vec of (id, desc)
+/// Redo command -- Applies undone operations again
+fn redo(args: Vec<cli::Arg>, store: &dyn Store) -> Result<(), Box<dyn Error>> {
+    replay(args, store, "redo", journal::Journal::redo)
 }
 
-/// Done command -- Mark todos as completed using hash prefixes
-fn done(args: Vec<cli::Arg>) -> Result<(), Box<dyn Error>> {
-    let mut todo_list = TodoList::load_from_file()?;
+/// Takes up to N steps through the journal, 1 by default
+fn replay(
+    args: Vec<cli::Arg>,
+    store: &dyn Store,
+    command: &str,
+    step: fn(&mut journal::Journal, &mut TodoList) -> Result<Option<String>, String>,
+) -> Result<(), Box<dyn Error>> {
+    let count = match args.iter().find_map(|arg| match arg {
+        cli::Arg::Value(value) => Some(value),
+        _ => None,
+    }) {
+        Some(count) => count
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or(format!(
+                "{command}: expected a number of operations, found '{count}'"
+            ))?,
+        None => 1,
+    };
 
-    let hashes: Vec<String> = args
-        .iter()
-        .filter_map(|arg| match arg {
-            cli::Arg::Value(value) => Some(value.clone()),
-            _ => None,
-        })
-        .collect();
+    let _lock = store.lock(command)?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
+    let mut journal = store.load_journal()?;
+
+    let mut done = Vec::new();
+    let mut failure = None;
+
+    for _ in 0..count {
+        match step(&mut journal, &mut todo_list) {
+            Ok(Some(description)) => done.push(description),
+            Ok(None) => break,
+            Err(e) => {
+                failure = Some(format!("{command}: {e}"));
+                break;
+            }
+        }
+    }
 
-    if hashes.is_empty() {
-        return Err("done: should at least specify one hash".into());
+    if !done.is_empty() {
+        store.save(&todo_list)?;
+        record_history(store, &before, &todo_list)?;
+        store.save_journal(&journal)?;
     }
 
-    let mut completed_count = 0;
-    let mut errors = Vec::new();
+    let verb = if command == "undo" { "Undid" } else { "Redid" };
+    for description in &done {
+        println!("{verb}: {description}");
+    }
 
-    for prefix in hashes {
-        if prefix.trim().is_empty() {
-            errors.push("Empty hash prefix provided".to_string());
-            continue;
+    match failure {
+        Some(e) => Err(e.into()),
+        None if done.is_empty() => Err(format!("{command}: nothing to {command}").into()),
+        None => Ok(()),
+    }
+}
+
+/// History command -- Shows what happened to one todo or to the whole list,
+/// or checks the history against the list with --check
+fn history(args: Vec<cli::Arg>, store: &dyn Store) -> Result<(), Box<dyn Error>> {
+    let events = store.load_history()?;
+
+    if cli::Arg::get_flag(&args, &"check".to_string()) {
+        let todo_list = store.load()?;
+        let rebuilt = history::rebuild(&events);
+
+        if events.is_empty() && todo_list.items().is_empty() || rebuilt == todo_list.items() {
+            println!(
+                "History is consistent with the list ({} events, {})",
+                events.len(),
+                count_todos(rebuilt.len())
+            );
+            return Ok(());
         }
 
-        match todo_list.mark_done(&prefix) {
-            Ok(_) => {
-                completed_count += 1;
-            }
-            Err(MarkDoneError::NotFound(msg)) => {
-                errors.push(msg);
-            }
-            Err(MarkDoneError::AlreadyCompleted(msg)) => {
-                errors.push(msg);
-            }
-            Err(MarkDoneError::MultipleMatches(matched_prefix, matches)) => {
-                println!(
-                    "Multiple todos found matching '{matched_prefix}', please be more specific:",
-                );
-                for (hash, desc) in matches {
-                    println!("[{hash}] {desc}");
+        let mut problems = Vec::new();
+        for item in todo_list.items() {
+            match rebuilt.iter().find(|r| r.hash == item.hash) {
+                None => problems.push(format!("[{}] is missing from the history", item.hash)),
+                Some(r) if r != item => {
+                    problems.push(format!("[{}] differs from its history", item.hash))
                 }
+                _ => {}
             }
         }
-    }
-
-    if completed_count > 0 {
-        todo_list.save_to_file()?;
-    }
-
-    if !errors.is_empty() {
-        for error in &errors {
-            eprintln!("{error}");
+        for item in &rebuilt {
+            if !todo_list.items().iter().any(|i| i.hash == item.hash) {
+                problems.push(format!(
+                    "[{}] is in the history but not in the list",
+                    item.hash
+                ));
+            }
         }
-        if completed_count == 0 {
-            return Err("No todos were marked as done".into());
+        if problems.is_empty() {
+            problems.push("the todos are in a different order".to_string());
+        }
+
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        return Err("history: the history does not match the list".into());
+    }
+
+    let prefix = args.iter().find_map(|arg| match arg {
+        cli::Arg::Value(value) => Some(value.clone()),
+        _ => None,
+    });
+
+    let selected: Vec<&history::Event> = match prefix {
+        Some(prefix) => {
+            let mut hashes: Vec<&str> = events
+                .iter()
+                .filter_map(|e| e.hash.as_deref())
+                .filter(|hash| hash.starts_with(prefix.as_str()))
+                .collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+
+            let hash = match hashes.as_slice() {
+                [hash] => hash.to_string(),
+                [] => return Err(format!("history: no todo found with hash '{prefix}'").into()),
+                _ => {
+                    return Err(format!(
+                        "history: multiple todos found matching '{prefix}', please be more specific"
+                    )
+                    .into());
+                }
+            };
+
+            events
+                .iter()
+                .filter(|e| e.hash.as_deref() == Some(hash.as_str()))
+                .collect()
+        }
+        None => events.iter().collect(),
+    };
+
+    if selected.is_empty() {
+        println!("No history yet");
+    }
+    for event in selected {
+        println!("{}", event.describe());
+    }
+
+    Ok(())
+}
+
+/// Batches of more todos than this have to be confirmed
+const CONFIRM_ABOVE: usize = 3;
+
+/// Done, start, wait, cancel, reopen and status commands -- Move todos to
+/// another state using hash prefixes, or filters for reopen. Without a
+/// `status`, the first value names it, as in `marc status review 1a2b`.
+fn transition(
+    args: Vec<cli::Arg>,
+    store: &dyn Store,
+    config: &Config,
+    command: &str,
+    status: Option<Status>,
+) -> Result<(), Box<dyn Error>> {
+    let mut hashes: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let status = match status {
+        Some(status) => status,
+        None if hashes.is_empty() => {
+            return Err(format!("{command}: expected '<status> <hash>...'").into());
+        }
+        None => Status::parse(&hashes.remove(0), &config.custom_statuses)
+            .map_err(|e| format!("{command}: {e}"))?,
+    };
+
+    let tags = cli::Arg::get_options(&args, &"tag".to_string());
+    let mut statuses = Vec::new();
+    for name in cli::Arg::get_options(&args, &"status".to_string()) {
+        statuses.push(
+            Status::parse(&name, &config.custom_statuses).map_err(|e| format!("{command}: {e}"))?,
+        );
+    }
+    let batch = !tags.is_empty() || !statuses.is_empty();
+
+    if hashes.is_empty() && !batch {
+        return Err(format!("{command}: should at least specify one hash").into());
+    }
+
+    let _lock = store.lock(command)?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
+
+    // Filters pick closed todos unless they name the states to pick
+    if batch {
+        let filter = Filter {
+            tags,
+            any_tag: cli::Arg::get_flag(&args, &"any".to_string()),
+            closed: statuses.is_empty().then_some(true),
+            statuses,
+            ..Filter::default()
+        };
+        let selected: Vec<TodoItem> = todo_list
+            .filtered(&filter)
+            .into_iter()
+            .filter(|item| item.status != status)
+            .collect();
+
+        if selected.is_empty() {
+            return Err(format!("{command}: no todos match the filters").into());
+        }
+        confirm_batch(
+            command,
+            &selected,
+            cli::Arg::get_flag(&args, &"yes".to_string()),
+            config,
+        )?;
+
+        hashes.extend(selected.into_iter().map(|item| item.hash));
+    }
+
+    let mut changed_count = 0;
+    let mut errors = Vec::new();
+
+    for prefix in hashes {
+        if prefix.trim().is_empty() {
+            errors.push("Empty hash prefix provided".to_string());
+            continue;
+        }
+
+        match todo_list.set_status(&prefix, status.clone(), config.cascade_completion) {
+            Ok(count) => {
+                changed_count += count;
+            }
+            Err(TransitionError::NotFound(msg)) => {
+                errors.push(msg);
+            }
+            Err(TransitionError::Invalid(msg)) => {
+                errors.push(msg);
+            }
+            Err(TransitionError::Blocked(desc, blockers)) => {
+                let mut msg = format!("warning: '{desc}' waits for:");
+                for (hash, desc) in blockers {
+                    msg.push_str(&format!("\n[{hash}] {desc}"));
+                }
+                errors.push(msg);
+            }
+            Err(TransitionError::OpenSubtasks(msg)) => {
+                errors.push(format!(
+                    "{msg} (or set subtasks.complete to 'cascade' to close them along)"
+                ));
+            }
+            Err(TransitionError::MultipleMatches(matched_prefix, matches)) => {
+                println!(
+                    "Multiple todos found matching '{matched_prefix}', please be more specific:",
+                );
+                for (hash, desc) in matches {
+                    println!("[{hash}] {desc}");
+                }
+            }
+        }
+    }
+
+    let verb = match status {
+        Status::Done => "completed".to_string(),
+        Status::Open => "reopened".to_string(),
+        Status::Started => "started".to_string(),
+        Status::Cancelled => "cancelled".to_string(),
+        Status::Waiting | Status::Custom(_) => format!("marked {status}"),
+    };
+
+    if changed_count > 0 {
+        let changed: Vec<TodoItem> = todo_list
+            .items()
+            .iter()
+            .filter(|item| {
+                before
+                    .items()
+                    .iter()
+                    .any(|b| b.hash == item.hash && b.status != item.status)
+            })
+            .cloned()
+            .collect();
+        // Recurring todos came back as new items at the end of the list
+        for item in &todo_list.items()[before.items().len()..] {
+            let due = item.due().map(|d| format!(" due {d}")).unwrap_or_default();
+            println!("Next: '{}'{due} [{}]", item.desc(), item.hash());
+        }
+
+        let description = format!("{verb} {}", describe(&changed));
+        commit(store, before, &todo_list, description, config)?;
+    }
+
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{error}");
+        }
+        if changed_count == 0 {
+            return Err(format!("No todos were {verb}").into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure a batch of todos picked by filters is meant, asking when it
+/// has more than a few of them unless `yes` is set
+fn confirm_batch(
+    command: &str,
+    selected: &[TodoItem],
+    yes: bool,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    if selected.len() <= CONFIRM_ABOVE || yes {
+        return Ok(());
+    }
+
+    if !config.interactive {
+        return Err(format!(
+            "{command}: the filters match {} todos, pass --yes to go ahead",
+            selected.len()
+        )
+        .into());
+    }
+    for item in selected {
+        println!("[{}] {}", item.hash(), item.desc());
+    }
+    if !confirm(&format!("{command} these {} todos?", selected.len()))? {
+        return Err(format!("{command}: cancelled").into());
+    }
+    Ok(())
+}
+
+/// Asks a yes or no question on the terminal
+fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Migrate command -- Converts the list between the JSON and SQLite backends
+fn migrate(args: Vec<cli::Arg>, config: &Config) -> Result<(), Box<dyn Error>> {
+    let to = match cli::Arg::get_option(&args, &"to".to_string()) {
+        Some(to) => Backend::from_str(&to).map_err(|e| format!("migrate: {e}"))?,
+        None => return Err("migrate: should specify a backend with --to <json|sqlite>".into()),
+    };
+    let force = cli::Arg::get_flag(&args, &"force".to_string());
+
+    let from = match to {
+        Backend::Json => Backend::Sqlite,
+        Backend::Sqlite => Backend::Json,
+    };
+
+    let source = config.open_backend(from)?;
+    let target = config.open_backend(to)?;
+
+    let _source_lock = source.lock("migrate")?;
+    let _target_lock = target.lock("migrate")?;
+
+    let todo_list = source.load()?;
+    let existing = target.load()?;
+
+    if !existing.items.is_empty() && !force {
+        return Err(format!(
+            "migrate: {} already contains {} todos, use --force to overwrite it",
+            target.location(),
+            existing.items.len()
+        )
+        .into());
+    }
+
+    target.save(&todo_list)?;
+
+    if target.has_history()? {
+        record_history(target.as_ref(), &existing, &todo_list)?;
+    } else {
+        target.append_history(&source.load_history()?)?;
+    }
+
+    if target.load()? != todo_list {
+        return Err(format!(
+            "migrate: {} does not match {} after migrating",
+            target.location(),
+            source.location()
+        )
+        .into());
+    }
+
+    println!(
+        "Migrated {} todos from {} to {}",
+        todo_list.items.len(),
+        source.location(),
+        target.location()
+    );
+
+    if config.backend != to {
+        let backend = format!("{to:?}").to_lowercase();
+        if config.interactive && confirm(&format!("Use the {backend} backend from now on?"))? {
+            let path = Config::write_setting("core.backend", &backend)?;
+            println!("Set core.backend to {backend} in {}", path.display());
+        } else {
+            println!("Run 'marc config set core.backend {backend}' to use it");
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists command -- Shows every list with its number of todos, the active one
+/// marked with a star
+fn lists(config: &Config) -> Result<(), Box<dyn Error>> {
+    let active = match config.single_file() {
+        Some(_) => None,
+        None => Some(config.list_name()),
+    };
+
+    let names = config.list_names()?;
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+
+    for name in names {
+        let todo_list = config.open_list(&name)?.load()?;
+        let done = todo_list
+            .items()
+            .iter()
+            .filter(|i| i.is_completed())
+            .count();
+
+        println!(
+            "{} {name:width$}  {} todos ({done} done)",
+            if active == Some(name.as_str()) {
+                "*"
+            } else {
+                " "
+            },
+            todo_list.items().len(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Init command -- Creates project lists in the given or current directory
+fn init(args: Vec<cli::Arg>, config: &Config) -> Result<(), Box<dyn Error>> {
+    let single = cli::Arg::get_flag(&args, &"single".to_string());
+
+    let dir = match args.iter().find_map(|arg| match arg {
+        cli::Arg::Value(value) => Some(value.clone()),
+        _ => None,
+    }) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::current_dir()?,
+    };
+
+    for existing in [config::PROJECT_DIR_NAME, config::PROJECT_FILE_NAME] {
+        if dir.join(existing).exists() {
+            return Err(format!("init: {} already exists", dir.join(existing).display()).into());
+        }
+    }
+
+    let project = if single {
+        if config.backend != Backend::Json {
+            return Err("init: --single only works with the JSON backend, \
+                use 'marc init' for a .marc directory"
+                .into());
+        }
+        Config {
+            file: Some(dir.join(config::PROJECT_FILE_NAME)),
+            ..config.clone()
+        }
+    } else {
+        Config {
+            dir: Some(dir.join(config::PROJECT_DIR_NAME)),
+            ..config.clone()
+        }
+    };
+
+    let store = project.open_store()?;
+    let _lock = store.lock("init")?;
+    store.save(&TodoList::new())?;
+
+    println!("Initialized project todos in {}", store.location());
+    Ok(())
+}
+
+/// List command -- Creates, renames and deletes named lists
+fn list_cmd(args: Vec<cli::Arg>, config: &Config) -> Result<(), Box<dyn Error>> {
+    let values: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    let force = cli::Arg::get_flag(&args, &"force".to_string());
+
+    let refuse_default = |name: &str, action: &str| -> Result<(), Box<dyn Error>> {
+        if name == config::DEFAULT_LIST {
+            return Err(format!("list: cannot {action} the '{name}' list").into());
+        }
+        Ok(())
+    };
+
+    match (
+        values.first().map(String::as_str),
+        values.get(1),
+        values.get(2),
+    ) {
+        (Some("create"), Some(name), None) => {
+            refuse_default(name, "create")?;
+            config::validate_list_name(name).map_err(|e| format!("list: {e}"))?;
+
+            if config.list_exists(name)? {
+                return Err(format!("list: '{name}' already exists").into());
+            }
+
+            let store = config.open_list(name)?;
+            let _lock = store.lock("list create")?;
+            store.save(&TodoList::new())?;
+            println!("Created list '{name}'");
+        }
+        (Some("rename"), Some(old), Some(new)) => {
+            refuse_default(old, "rename")?;
+            refuse_default(new, "rename a list to")?;
+            config::validate_list_name(new).map_err(|e| format!("list: {e}"))?;
+
+            if !config.list_exists(old)? {
+                return Err(Config::missing_list(old));
+            }
+            if config.list_exists(new)? {
+                return Err(format!("list: '{new}' already exists").into());
+            }
+
+            let store = config.open_list(old)?;
+            let _lock = store.lock("list rename")?;
+
+            for path in config.list_files(old)? {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                let renamed = path.with_file_name(format!("{new}{}", &file_name[old.len()..]));
+                fs::rename(&path, &renamed)?;
+            }
+
+            println!("Renamed list '{old}' to '{new}'");
+
+            if *old == config.default_list {
+                eprintln!(
+                    "warning: '{old}' was the default list, run 'marc config set core.default_list {new}'"
+                );
+            }
+        }
+        (Some("delete"), Some(name), None) => {
+            refuse_default(name, "delete")?;
+
+            if *name == config.default_list {
+                return Err(format!(
+                    "list: '{name}' is the default list (core.default_list), change it first"
+                )
+                .into());
+            }
+            if !config.list_exists(name)? {
+                return Err(Config::missing_list(name));
+            }
+
+            let store = config.open_list(name)?;
+            let _lock = store.lock("list delete")?;
+            let count = store.load()?.items().len();
+
+            if count > 0 && !force {
+                return Err(format!(
+                    "list: '{name}' still has {count} todos, use --force to delete it"
+                )
+                .into());
+            }
+
+            for path in config.list_files(name)? {
+                fs::remove_file(path)?;
+            }
+
+            println!("Deleted list '{name}'");
+        }
+        _ => {
+            return Err(
+                "list: expected 'create <name>', 'rename <old> <new>' or 'delete <name>'".into(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Block and unblock commands -- Make a todo wait for others, or stop
+/// waiting
+fn block(
+    args: Vec<cli::Arg>,
+    store: &dyn Store,
+    config: &Config,
+    blocking: bool,
+) -> Result<(), Box<dyn Error>> {
+    let command = if blocking { "block" } else { "unblock" };
+    let hashes: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let [prefix, blockers @ ..] = hashes.as_slice() else {
+        return Err(format!("{command}: expected '<todo> <blocker>...'").into());
+    };
+    if blockers.is_empty() {
+        return Err(format!("{command}: expected '<todo> <blocker>...'").into());
+    }
+
+    let _lock = store.lock(command)?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
+    let now = date::now();
+
+    let index = resolve(&todo_list, prefix, command)?;
+    for blocker in blockers {
+        let blocker = todo_list.items()[resolve(&todo_list, blocker, command)?]
+            .hash()
+            .to_string();
+
+        if blocking {
+            todo_list
+                .add_dependency(index, &blocker, now)
+                .map_err(|e| format!("{command}: {e}"))?;
+        } else {
+            let item = &mut todo_list.items[index];
+            if item.depends_on.contains(&blocker) {
+                item.depends_on.retain(|h| *h != blocker);
+                item.updated_at = now;
+            }
+        }
+    }
+
+    let item = &todo_list.items()[index];
+    todo_list.print_item(item, 0, config.color, now);
+
+    let description = if blocking {
+        format!("made {} wait", describe(std::slice::from_ref(item)))
+    } else {
+        format!("unblocked {}", describe(std::slice::from_ref(item)))
+    };
+    commit(store, before, &todo_list, description, config)
+}
+
+/// Graph command -- Prints the dependencies between todos in Graphviz DOT
+fn graph(store: &dyn Store) -> Result<(), Box<dyn Error>> {
+    let todo_list = store.load()?;
+    let involved = |item: &TodoItem| {
+        !item.depends_on().is_empty()
+            || todo_list
+                .items()
+                .iter()
+                .any(|other| other.depends_on().iter().any(|h| h == item.hash()))
+    };
+
+    println!("digraph marc {{");
+    println!("    rankdir=LR;");
+    println!("    node [shape=box];");
+
+    for item in todo_list.items().iter().filter(|item| involved(item)) {
+        let style = if item.is_closed() {
+            ", style=dashed, fontcolor=gray"
+        } else {
+            ""
+        };
+        println!(
+            "    \"{}\" [label=\"{}\"{style}];",
+            item.hash(),
+            dot_escape(item.desc())
+        );
+    }
+
+    // Edges go from the blocker to the todo waiting for it
+    for item in todo_list.items() {
+        for blocker in item.depends_on() {
+            if todo_list.items().iter().any(|i| i.hash() == blocker) {
+                println!("    \"{blocker}\" -> \"{}\";", item.hash());
+            }
+        }
+    }
+
+    println!("}}");
+    Ok(())
+}
+
+/// Recur command -- Lists the recurring todos, shows the series of one,
+/// gives it a rule or stops it
+fn recur(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let values: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    let stop = cli::Arg::get_flag(&args, &"stop".to_string());
+    let now = date::now();
+
+    match values.as_slice() {
+        [] if !stop => {
+            let todo_list = store.load()?;
+            let entries: Vec<TodoItem> = todo_list
+                .items()
+                .iter()
+                .filter(|item| !item.is_closed() && item.recur().is_some())
+                .cloned()
+                .collect();
+            TodoList::list_items(&entries, &todo_list, config.color, now);
+            Ok(())
+        }
+        [prefix] if !stop => {
+            let todo_list = store.load()?;
+            let index = resolve(&todo_list, prefix, "recur")?;
+            let series = todo_list.items()[index].series();
+            let entries: Vec<TodoItem> = todo_list
+                .series_items(series)
+                .into_iter()
+                .cloned()
+                .collect();
+            TodoList::list_items(&entries, &todo_list, config.color, now);
+            Ok(())
+        }
+        [prefix] => {
+            let _lock = store.lock("recur")?;
+            let mut todo_list = store.load()?;
+            let before = todo_list.clone();
+            let index = resolve(&todo_list, prefix, "recur")?;
+            let series = todo_list.items()[index].series().to_string();
+
+            let mut stopped = Vec::new();
+            for item in &mut todo_list.items {
+                if item.series() == series && !item.is_closed() && item.recur.is_some() {
+                    item.recur = None;
+                    item.updated_at = now;
+                    stopped.push(item.clone());
+                }
+            }
+            if stopped.is_empty() {
+                let desc = todo_list.items()[index].desc();
+                return Err(format!("recur: '{desc}' does not recur").into());
+            }
+
+            for item in &stopped {
+                println!("Stopped: '{}' [{}]", item.desc(), item.hash());
+            }
+            let description = format!("stopped {} recurring", describe(&stopped));
+            commit(store, before, &todo_list, description, config)
+        }
+        [prefix, rule] if !stop => {
+            let recur = Recurrence::from_str(rule).map_err(|e| format!("recur: {e}"))?;
+            let _lock = store.lock("recur")?;
+            let mut todo_list = store.load()?;
+            let before = todo_list.clone();
+            let index = resolve(&todo_list, prefix, "recur")?;
+
+            let item = &mut todo_list.items[index];
+            if item.is_closed() {
+                return Err(format!("recur: '{}' is already {}", item.desc, item.status).into());
+            }
+            if item.due.is_none() {
+                item.due = recur.first(date::Date::from_timestamp(now));
+            }
+            item.recur = Some(recur);
+            item.updated_at = now;
+
+            let item = &todo_list.items()[index];
+            todo_list.print_item(item, 0, config.color, now);
+            let description = format!("made {} recur", describe(std::slice::from_ref(item)));
+            commit(store, before, &todo_list, description, config)
+        }
+        _ => Err("recur: expected '[<todo>] [<rule>]' or '<todo> --stop'".into()),
+    }
+}
+
+/// `text` as the inside of a quoted DOT string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Tag command -- Lists, declares, renames, merges and removes tags
+fn tag_cmd(args: Vec<cli::Arg>, store: &dyn Store, config: &Config) -> Result<(), Box<dyn Error>> {
+    let values: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    let create = cli::Arg::get_flag(&args, &"create".to_string());
+    let prune = cli::Arg::get_flag(&args, &"prune".to_string());
+    let color = cli::Arg::get_option(&args, &"color".to_string());
+    let description = cli::Arg::get_option(&args, &"description".to_string());
+    let to = cli::Arg::get_option(&args, &"to".to_string());
+    let strip = cli::Arg::get_flag(&args, &"strip".to_string());
+
+    if values.is_empty() && !create && !prune {
+        let todo_list = store.load()?;
+        let counts = todo_list.tag_counts();
+
+        if counts.is_empty() {
+            println!("No tags");
+            return Ok(());
+        }
+
+        // Subtags go under their parent, indented and by their last name
+        let mut tree: Vec<(&String, String)> = counts
+            .keys()
+            .map(|tag| {
+                let label = match tag.rsplit_once('/') {
+                    Some((_, name)) => format!("{}{name}", "  ".repeat(tag.matches('/').count())),
+                    None => format!("#{tag}"),
+                };
+                (tag, label)
+            })
+            .collect();
+        tree.sort_by(|(a, _), (b, _)| a.split('/').cmp(b.split('/')));
+
+        let width = tree
+            .iter()
+            .map(|(_, label)| label.chars().count())
+            .max()
+            .unwrap_or_default();
+        for (tag, label) in tree {
+            let (open, done) = counts[tag];
+            let name = todo::paint(config.color, todo::tag_color(todo_list.tags(), tag), &label);
+            let padding = " ".repeat(width - label.chars().count());
+            let description = todo_list
+                .tags()
+                .get(tag)
+                .and_then(|info| info.description.as_deref())
+                .map(|d| format!("  {d}"))
+                .unwrap_or_default();
+            println!("{name}{padding}  {open} open, {done} done{description}");
+        }
+        return Ok(());
+    }
+
+    let _lock = store.lock("tag")?;
+    let mut todo_list = store.load()?;
+    let before = todo_list.clone();
+    let now = date::now();
+
+    let known = |todo_list: &TodoList, tag: &str| todo_list.tag_counts().contains_key(tag);
+    let warn_if_configured = |tag: &str| {
+        if config.default_tag.as_deref() == Some(tag) {
+            eprintln!(
+                "warning: #{tag} is the default tag (add.default_tag), update it with 'marc config set'"
+            );
+        }
+    };
+
+    let description = match (create, prune, values.as_slice()) {
+        (true, false, [name]) => {
+            todo::validate_tag(name).map_err(|e| format!("tag: {e}"))?;
+            if let Some(color) = &color {
+                TagInfo::validate_color(color).map_err(|e| format!("tag: {e}"))?;
+            }
+
+            let declared = todo_list.tags.contains_key(name);
+            let info = todo_list.tags.entry(name.clone()).or_default();
+            if color.is_some() {
+                info.color = color;
+            }
+            if description.is_some() {
+                info.description = description;
+            }
+
+            if declared {
+                println!("Updated #{name}");
+                format!("updated #{name}")
+            } else {
+                println!("Created #{name}");
+                format!("created #{name}")
+            }
+        }
+        (false, true, []) => {
+            let counts = todo_list.tag_counts();
+            let unused: Vec<String> = todo_list
+                .tags
+                .keys()
+                .filter(|tag| {
+                    counts
+                        .get(*tag)
+                        .is_some_and(|&(open, done)| open + done == 0)
+                })
+                .cloned()
+                .collect();
+
+            for tag in &unused {
+                todo_list.tags.remove(tag);
+                println!("Pruned #{tag}");
+            }
+            if unused.is_empty() {
+                println!("No unused tags");
+            }
+
+            match unused.len() {
+                1 => "pruned 1 tag".to_string(),
+                n => format!("pruned {n} tags"),
+            }
+        }
+        (false, false, [verb, old, new]) if verb == "rename" => {
+            todo::validate_tag(new).map_err(|e| format!("tag: {e}"))?;
+            if !known(&todo_list, old) {
+                return Err(format!("tag: no tag named '{old}'").into());
+            }
+            if known(&todo_list, new) {
+                return Err(format!(
+                    "tag: '{new}' already exists, use 'marc tag merge {old} {new}' to merge them"
+                )
+                .into());
+            }
+
+            let count = todo_list.retag(old, Some(new), now);
+            println!("Renamed #{old} to #{new} on {}", count_todos(count));
+            warn_if_configured(old);
+            format!("renamed #{old} to #{new}")
+        }
+        (false, false, [verb, from, into]) if verb == "merge" => {
+            todo::validate_tag(into).map_err(|e| format!("tag: {e}"))?;
+            if todo::is_within(into, from) {
+                return Err("tag: cannot merge a tag into itself or one of its subtags".into());
+            }
+            if !known(&todo_list, from) {
+                return Err(format!("tag: no tag named '{from}'").into());
+            }
+
+            let count = todo_list.retag(from, Some(into), now);
+            println!("Merged #{from} into #{into} on {}", count_todos(count));
+            warn_if_configured(from);
+            format!("merged #{from} into #{into}")
+        }
+        (false, false, [verb, name]) if verb == "rm" => {
+            if !known(&todo_list, name) {
+                return Err(format!("tag: no tag named '{name}'").into());
+            }
+
+            let (open, done) = todo_list.tag_counts()[name.as_str()];
+            let count = open + done;
+            match (&to, strip) {
+                (Some(_), true) => {
+                    return Err("tag: use either --to <tag> or --strip, not both".into());
+                }
+                (None, false) if count > 0 => {
+                    return Err(format!(
+                        "tag: {} tagged #{name}, use --to <tag> to retag them or --strip to untag them",
+                        count_todos(count)
+                    )
+                    .into());
+                }
+                (Some(to), false) => {
+                    todo::validate_tag(to).map_err(|e| format!("tag: {e}"))?;
+                    if todo::is_within(to, name) {
+                        return Err("tag: cannot retag todos with the tag being removed".into());
+                    }
+                }
+                _ => {}
+            }
+
+            todo_list.retag(name, to.as_deref(), now);
+            match &to {
+                Some(to) if count > 0 => {
+                    println!("Removed #{name}, retagged {} #{to}", count_todos(count))
+                }
+                None if count > 0 => println!("Removed #{name} from {}", count_todos(count)),
+                _ => println!("Removed #{name}"),
+            }
+            warn_if_configured(name);
+            format!("removed #{name}")
+        }
+        _ => {
+            return Err("tag: expected no arguments, '--create <tag>', '--prune', \
+                        'rename <old> <new>', 'merge <from> <into>' or 'rm <tag>'"
+                .into());
+        }
+    };
+
+    commit(store, before, &todo_list, description, config)
+}
+
+/// Move command -- Moves todos from the active list to another one
+fn move_items(
+    args: Vec<cli::Arg>,
+    store: &dyn Store,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let to = cli::Arg::get_option(&args, &"to".to_string())
+        .ok_or("move: should specify a list with --to <list>")?;
+
+    let hashes: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+
+    if hashes.is_empty() {
+        return Err("move: should at least specify one hash".into());
+    }
+
+    if !config.list_exists(&to)? {
+        return Err(Config::missing_list(&to));
+    }
+
+    let target = config.open_list(&to)?;
+
+    if target.location() == store.location() {
+        return Err(format!("move: the todos are already in '{to}'").into());
+    }
+
+    // Both lists are locked, always in the same order so two moves in
+    // opposite directions cannot deadlock
+    let (first, second) = if store.location() < target.location() {
+        (store, target.as_ref())
+    } else {
+        (target.as_ref(), store)
+    };
+    let _first_lock = first.lock("move")?;
+    let _second_lock = second.lock("move")?;
+
+    let mut source_list = store.load()?;
+    let mut target_list = target.load()?;
+    let (source_before, target_before) = (source_list.clone(), target_list.clone());
+
+    let mut moved = Vec::new();
+    for prefix in &hashes {
+        let index = resolve(&source_list, prefix, "move")?;
+        if !moved.contains(&index) {
+            moved.push(index);
+        }
+    }
+
+    // Subtasks go along with their parent
+    for index in moved.clone() {
+        for hash in source_list.descendants(source_list.items()[index].hash()) {
+            let index = resolve(&source_list, &hash, "move")?;
+            if !moved.contains(&index) {
+                moved.push(index);
+            }
+        }
+    }
+
+    moved.sort_unstable();
+    let mut items: Vec<TodoItem> = moved
+        .iter()
+        .map(|&i| source_list.items[i].clone())
+        .collect();
+
+    // Todos moved without their parent become top-level ones, and
+    // dependencies between the lists are dropped
+    let now = date::now();
+    let hashes: Vec<String> = items.iter().map(|i| i.hash().to_string()).collect();
+    for item in &mut items {
+        if item.parent.as_ref().is_some_and(|p| !hashes.contains(p)) {
+            item.parent = None;
+            item.updated_at = now;
+        }
+        if item.depends_on.iter().any(|h| !hashes.contains(h)) {
+            item.depends_on.retain(|h| hashes.contains(h));
+            item.updated_at = now;
+        }
+    }
+
+    for item in items {
+        if target_list.items().iter().any(|i| i.hash() == item.hash()) {
+            return Err(format!("move: '{to}' already has a todo [{}]", item.hash()).into());
+        }
+
+        source_list.items.retain(|i| i.hash() != item.hash());
+        target_list.items.push(item);
+    }
+    source_list.forget_dependencies(&hashes, now);
+
+    // Saving the target first means an interruption leaves the todos in
+    // both lists rather than in neither
+    let moved = &target_list.items()[target_before.items().len()..];
+    let from = match config.single_file() {
+        Some(file) => file.display().to_string(),
+        None => config.list_name().to_string(),
+    };
+    let description = format!("moved {} from '{from}'", describe(moved));
+    commit(
+        target.as_ref(),
+        target_before,
+        &target_list,
+        description,
+        config,
+    )?;
+
+    let description = format!("moved {} to '{to}'", describe(moved));
+    commit(store, source_before, &source_list, description, config)?;
+
+    for item in moved {
+        println!("Moved [{}] '{}' to '{to}'", item.hash(), item.desc());
+    }
+
+    Ok(())
+}
+
+/// Config command -- Shows and changes settings
+fn config_cmd(args: Vec<cli::Arg>, config: &Config) -> Result<(), Box<dyn Error>> {
+    let values: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            cli::Arg::Value(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+
+    match values.first().map(String::as_str) {
+        None | Some("list") => {
+            for key in config::KEYS {
+                match config.settings.get(key.name) {
+                    Some((value, origin)) => {
+                        println!("{} = {value}  # {} ({origin})", key.name, key.description)
+                    }
+                    None => println!("# {} is not set, {}", key.name, key.description),
+                }
+            }
+        }
+        Some("get") => {
+            let name = values.get(1).ok_or("config: get should specify a key")?;
+
+            if !config::KEYS.iter().any(|key| key.name == name) {
+                return Err(format!("config: unknown key '{name}'").into());
+            }
+
+            if let Some((value, _)) = config.settings.get(name) {
+                println!("{value}");
+            }
+        }
+        Some("set") => {
+            let (name, value) = match (values.get(1), values.get(2)) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err("config: set should specify a key and a value".into()),
+            };
+
+            let path = Config::write_setting(name, value).map_err(|e| format!("config: {e}"))?;
+            println!("Set {name} = {value} in {}", path.display());
+        }
+        Some(other) => {
+            return Err(
+                format!("config: unknown action '{other}', expected get, set or list").into(),
+            );
         }
     }
 
@@ -508,3 +1984,241 @@ fn version() {
     let name = env!("CARGO_PKG_NAME");
     println!("{name} version {env}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[&str]) -> Vec<cli::Arg> {
+        values
+            .iter()
+            .map(|value| cli::Arg::Value(value.to_string()))
+            .collect()
+    }
+
+    fn option(name: &str, value: &str) -> cli::Arg {
+        cli::Arg::Option {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// A list of todos with the given hashes, descriptions and tags
+    fn store(todos: &[(&str, &str, &[&str])]) -> MemoryStore {
+        let mut list = TodoList::new();
+        for (hash, desc, tags) in todos {
+            let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+            list.add_item(desc.to_string(), &tags, 7).hash = hash.to_string();
+        }
+        MemoryStore::with_list(list)
+    }
+
+    fn item(store: &MemoryStore, hash: &str) -> TodoItem {
+        let list = store.load().unwrap();
+        list.items()
+            .iter()
+            .find(|item| item.hash() == hash)
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn add_refuses_invalid_tags() {
+        for tag in ["a b", "/x"] {
+            let store = store(&[]);
+            let mut args = values(&["x"]);
+            args.push(option("tag", tag));
+
+            let err = add(args, &store, &Config::defaults()).unwrap_err();
+
+            assert!(err.to_string().starts_with("add: invalid tag"), "{err}");
+            assert!(store.load().unwrap().items().is_empty());
+        }
+    }
+
+    #[test]
+    fn modify_reports_each_changed_field() {
+        let old = store(&[("0ca2285", "old", &["work"])])
+            .load()
+            .unwrap()
+            .items[0]
+            .clone();
+        let mut new = old.clone();
+        new.desc = "new".to_string();
+        new.tags = vec!["home".to_string()];
+        new.priority = Some(Priority::High);
+
+        assert_eq!(
+            field_changes(&old, &new),
+            [
+                "description: 'old' -> 'new'",
+                "tags: #work -> #home",
+                "priority: none -> H",
+            ]
+        );
+        assert!(field_changes(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn modify_adds_and_removes_tags() {
+        let store = store(&[("0ca2285", "a", &["work", "someday"])]);
+
+        let args = values(&["0ca2285", "+home", "-someday"]);
+        modify(args, &store, &Config::defaults()).unwrap();
+
+        assert_eq!(item(&store, "0ca2285").tags(), ["work", "home"]);
+    }
+
+    #[test]
+    fn modify_refuses_invalid_tags() {
+        let store = store(&[("0ca2285", "a", &["work"])]);
+        let mut args = values(&["0ca2285"]);
+        args.push(option("tag", "a b"));
+
+        let err = modify(args, &store, &Config::defaults()).unwrap_err();
+
+        assert!(err.to_string().starts_with("modify: invalid tag"), "{err}");
+        assert_eq!(item(&store, "0ca2285").tags(), ["work"]);
+    }
+
+    #[test]
+    fn modify_takes_short_hex_words_for_the_description() {
+        let store = store(&[("0ca2285", "old", &[]), ("ab199c7", "other", &[])]);
+
+        let args = values(&["0ca2285", "a", "new", "description"]);
+        modify(args, &store, &Config::defaults()).unwrap();
+        let args = values(&["0ca2285", "--", "cafe", "hours"]);
+        modify(args, &store, &Config::defaults()).unwrap();
+
+        assert_eq!(item(&store, "0ca2285").desc(), "cafe hours");
+        assert_eq!(item(&store, "ab199c7").desc(), "other");
+    }
+
+    #[test]
+    fn modify_gives_a_description_to_one_todo_only() {
+        let store = store(&[("0ca2285", "a", &[]), ("ab199c7", "b", &[])]);
+
+        let args = values(&["0ca2285", "ab19", "same", "text"]);
+        let error = modify(args, &store, &Config::defaults()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "modify: a new description can only be given to one todo"
+        );
+        assert_eq!(item(&store, "0ca2285").desc(), "a");
+    }
+
+    #[test]
+    fn modify_batches_need_yes_without_a_terminal() {
+        let store = store(&[
+            ("1111111", "a", &["work"]),
+            ("2222222", "b", &["work"]),
+            ("3333333", "c", &["work"]),
+            ("4444444", "d", &["work"]),
+            ("5555555", "e", &[]),
+        ]);
+        let config = Config::defaults();
+
+        let args = vec![option("with_tag", "work"), option("priority", "H")];
+        let error = modify(args, &store, &config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "modify: the filters match 4 todos, pass --yes to go ahead"
+        );
+        assert_eq!(item(&store, "1111111").priority(), None);
+
+        let mut args = vec![option("with_tag", "work"), option("priority", "H")];
+        args.push(cli::Arg::Flag("yes".to_string()));
+        modify(args, &store, &config).unwrap();
+        assert_eq!(item(&store, "4444444").priority(), Some(Priority::High));
+        assert_eq!(item(&store, "5555555").priority(), None);
+
+        transition(
+            values(&["3333"]),
+            &store,
+            &config,
+            "start",
+            Some(Status::Started),
+        )
+        .unwrap();
+        let args = vec![option("with_status", "started"), option("tag", "now")];
+        modify(args, &store, &config).unwrap();
+        assert_eq!(item(&store, "3333333").tags(), ["now"]);
+        assert_eq!(item(&store, "2222222").tags(), ["work"]);
+    }
+
+    /// Five todos tagged `work` and one tagged `home`, all done
+    fn done_store() -> MemoryStore {
+        let store = store(&[
+            ("1111111", "a", &["work"]),
+            ("2222222", "b", &["work"]),
+            ("3333333", "c", &["work"]),
+            ("4444444", "d", &["work"]),
+            ("5555555", "e", &["work"]),
+            ("6666666", "f", &["home"]),
+        ]);
+        let mut list = store.load().unwrap();
+        for hash in ["1111", "2222", "3333", "4444", "5555", "6666"] {
+            list.mark_done(hash, false).unwrap();
+        }
+        store.save(&list).unwrap();
+        store
+    }
+
+    fn open_descs(store: &MemoryStore) -> Vec<String> {
+        store
+            .load()
+            .unwrap()
+            .items()
+            .iter()
+            .filter(|item| !item.is_closed())
+            .map(|item| item.desc().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reopen_batches_picked_by_filters() {
+        let store = done_store();
+        let config = Config::defaults();
+        let mut list = store.load().unwrap();
+        list.set_status("2222", Status::Open, false).unwrap();
+        list.set_status("2222", Status::Cancelled, false).unwrap();
+        store.save(&list).unwrap();
+
+        let args = vec![option("status", "cancelled"), option("tag", "work")];
+        transition(args, &store, &config, "reopen", Some(Status::Open)).unwrap();
+        assert_eq!(open_descs(&store), ["b"]);
+
+        let args = vec![
+            option("tag", "home"),
+            option("tag", "nothing"),
+            cli::Arg::Flag("any".to_string()),
+        ];
+        transition(args, &store, &config, "reopen", Some(Status::Open)).unwrap();
+        assert_eq!(open_descs(&store), ["b", "f"]);
+    }
+
+    #[test]
+    fn large_batches_need_yes_without_a_terminal() {
+        let store = done_store();
+        let config = Config::defaults();
+
+        let error = transition(
+            vec![option("tag", "work")],
+            &store,
+            &config,
+            "reopen",
+            Some(Status::Open),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "reopen: the filters match 5 todos, pass --yes to go ahead"
+        );
+        assert!(open_descs(&store).is_empty());
+
+        let args = vec![option("tag", "work"), cli::Arg::Flag("yes".to_string())];
+        transition(args, &store, &config, "reopen", Some(Status::Open)).unwrap();
+        assert_eq!(open_descs(&store), ["a", "b", "c", "d", "e"]);
+    }
+}
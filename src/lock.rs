@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Files this process currently holds a lock on
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Advisory lock on a todo file, released when dropped.
+///
+/// The lock lives on a `<file>.lock` sidecar so the data file itself can be
+/// replaced by a rename while the lock is held. The sidecar records the pid
+/// and command of the holder so a waiting process can say who it waits for.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    target: PathBuf,
+}
+
+impl FileLock {
+    /// Waits up to `timeout` for the lock on `target`
+    pub fn acquire(
+        target: &Path,
+        timeout: Duration,
+        command: &str,
+    ) -> Result<FileLock, Box<dyn Error>> {
+        let lock_path = lock_path(target);
+        let file = open(&lock_path)?;
+        let start = Instant::now();
+
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Self::locked(file, target, command),
+                Err(TryLockError::WouldBlock) => {
+                    if start.elapsed() >= timeout {
+                        return Err(format!(
+                            "error: timed out after {}s waiting for the lock on {}; {}",
+                            timeout.as_secs_f32(),
+                            target.display(),
+                            describe_holder(&lock_path)
+                        )
+                        .into());
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(format!(
+                        "error: failed to lock todo file ({}): {e}",
+                        lock_path.display()
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    /// Takes the lock on `target` only if nobody else holds it
+    pub fn try_acquire(target: &Path, command: &str) -> Result<Option<FileLock>, Box<dyn Error>> {
+        let file = open(&lock_path(target))?;
+
+        match file.try_lock() {
+            Ok(()) => Self::locked(file, target, command).map(Some),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Whether this process already holds the lock on `target`
+    pub fn is_held(target: &Path) -> bool {
+        HELD.lock()
+            .map(|held| held.iter().any(|p| p == target))
+            .unwrap_or(false)
+    }
+
+    fn locked(mut file: File, target: &Path, command: &str) -> Result<FileLock, Box<dyn Error>> {
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{} marc {command}", process::id())?;
+        file.flush()?;
+
+        if let Ok(mut held) = HELD.lock() {
+            held.push(target.to_path_buf());
+        }
+
+        Ok(FileLock {
+            file,
+            target: target.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Ok(mut held) = HELD.lock()
+            && let Some(i) = held.iter().position(|p| *p == self.target)
+        {
+            held.remove(i);
+        }
+
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+fn lock_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn open(lock_path: &Path) -> Result<File, Box<dyn Error>> {
+    if let Some(dir) = lock_path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        fs::create_dir_all(dir).map_err(|e| {
+            format!(
+                "error: failed to create the directory of the lock file ({}): {e}",
+                dir.display()
+            )
+        })?;
+    }
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)
+        .map_err(|e| {
+            format!(
+                "error: failed to open lock file ({}): {e}",
+                lock_path.display()
+            )
+            .into()
+        })
+}
+
+fn describe_holder(lock_path: &Path) -> String {
+    let holder = fs::read_to_string(lock_path).unwrap_or_default();
+
+    match holder.trim().split_once(' ') {
+        Some((pid, command)) => format!("it is held by process {pid} ({command})"),
+        None => "it is held by another process".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn second_lock_times_out_and_names_the_holder() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("marc.json");
+
+        let _held = FileLock::acquire(&target, Duration::ZERO, "edit").unwrap();
+        let err = FileLock::acquire(&target, Duration::ZERO, "add").unwrap_err();
+
+        let expected = format!("held by process {} (marc edit)", process::id());
+        assert!(err.to_string().contains(&expected), "{err}");
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("marc.json");
+
+        let held = FileLock::acquire(&target, Duration::ZERO, "add").unwrap();
+        assert!(FileLock::is_held(&target));
+        drop(held);
+
+        assert!(!FileLock::is_held(&target));
+        assert!(FileLock::try_acquire(&target, "add").unwrap().is_some());
+    }
+}
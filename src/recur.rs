@@ -0,0 +1,282 @@
+//! Recurrence rules of todos that come back once done.
+//!
+//! Rules are written the same way on the command line and in the todo file:
+//! `daily`, `weekly`, `weekly:mon,thu`, `monthly`, `monthly:15` or
+//! `after:3d`.
+
+use crate::date::{self, Date};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+const WEEKDAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Recurrence {
+    Daily,
+    /// On these weekdays, from 0 for Monday, or on the weekday of the due
+    /// date when empty
+    Weekly(Vec<u64>),
+    /// On this day of the month, or on the day of the due date. Short months
+    /// use their last day.
+    Monthly(Option<u64>),
+    /// This many days after the todo was done
+    AfterCompletion(u64),
+}
+
+impl Recurrence {
+    /// The first day on or after `today` the rule falls on, for todos added
+    /// without a due date. Like every day a rule gives, nothing if it would
+    /// be past the last day a date can have.
+    pub fn first(&self, today: Date) -> Option<Date> {
+        match self {
+            Recurrence::AfterCompletion(_) => Some(today),
+            _ => self.after(today, today, true),
+        }
+    }
+
+    /// When the next instance of a todo due on `due` and done on `done_on`
+    /// is due. Scheduled rules keep their cadence but never give a day that
+    /// has already passed.
+    pub fn next_due(&self, due: Option<Date>, done_on: Date) -> Option<Date> {
+        match self {
+            Recurrence::AfterCompletion(days) => done_on.add_days(*days),
+            _ => {
+                let anchor = due.map_or(done_on, |due| due.max(done_on));
+                self.after(anchor, due.unwrap_or(done_on), false)
+            }
+        }
+    }
+
+    /// The first day after `anchor`, or from it when `inclusive`, that a
+    /// scheduled rule falls on. `reference` gives the weekday or day of the
+    /// month when the rule does not name one.
+    fn after(&self, anchor: Date, reference: Date, inclusive: bool) -> Option<Date> {
+        let start = if inclusive { 0 } else { 1 };
+
+        match self {
+            Recurrence::Daily => anchor.add_days(start),
+            Recurrence::Weekly(days) => {
+                let days = match days.as_slice() {
+                    [] => vec![reference.weekday()],
+                    days => days.to_vec(),
+                };
+                let offset = (start..start + 7)
+                    .find(|offset| days.contains(&((anchor.weekday() + offset) % 7)))
+                    .unwrap_or(7);
+                anchor.add_days(offset)
+            }
+            Recurrence::Monthly(day) => {
+                let day = day.unwrap_or(reference.ymd().2);
+                let this_month = anchor.with_day(day);
+                let in_time = if inclusive {
+                    this_month >= anchor
+                } else {
+                    this_month > anchor
+                };
+
+                if in_time {
+                    Some(this_month)
+                } else {
+                    Some(anchor.with_day(1).add_months(1)?.with_day(day))
+                }
+            }
+            Recurrence::AfterCompletion(days) => anchor.add_days(*days),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekly(days) if days.is_empty() => write!(f, "weekly"),
+            Recurrence::Weekly(days) => {
+                let names: Vec<&str> = days.iter().map(|&d| WEEKDAY_NAMES[d as usize]).collect();
+                write!(f, "weekly:{}", names.join(","))
+            }
+            Recurrence::Monthly(None) => write!(f, "monthly"),
+            Recurrence::Monthly(Some(day)) => write!(f, "monthly:{day}"),
+            Recurrence::AfterCompletion(days) if days % 7 == 0 => write!(f, "after:{}w", days / 7),
+            Recurrence::AfterCompletion(days) => write!(f, "after:{days}d"),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid recurrence '{s}', expected daily, weekly, weekly:mon,thu, monthly, \
+                 monthly:15 or after:3d"
+            )
+        };
+        let text = s.trim().to_lowercase();
+        let (kind, arguments) = match text.split_once(':') {
+            Some((kind, arguments)) => (kind, Some(arguments)),
+            None => (text.as_str(), None),
+        };
+
+        match (kind, arguments) {
+            ("daily", None) => Ok(Recurrence::Daily),
+            ("weekly", None) => Ok(Recurrence::Weekly(Vec::new())),
+            ("weekly", Some(names)) => {
+                let mut days = names
+                    .split(',')
+                    .map(|name| date::weekday(name.trim()).ok_or_else(invalid))
+                    .collect::<Result<Vec<u64>, String>>()?;
+                days.sort_unstable();
+                days.dedup();
+                Ok(Recurrence::Weekly(days))
+            }
+            ("monthly", None) => Ok(Recurrence::Monthly(None)),
+            ("monthly", Some(day)) => match day.parse::<u64>() {
+                Ok(day @ 1..=31) => Ok(Recurrence::Monthly(Some(day))),
+                _ => Err(invalid()),
+            },
+            ("after", Some(period)) => {
+                let (count, days) = if let Some(count) = period.strip_suffix('d') {
+                    (count, 1)
+                } else if let Some(count) = period.strip_suffix('w') {
+                    (count, 7)
+                } else {
+                    return Err(invalid());
+                };
+                match count.parse::<u64>().ok().and_then(|c| c.checked_mul(days)) {
+                    Some(days) if days > 0 => Ok(Recurrence::AfterCompletion(days)),
+                    _ => Err(invalid()),
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> String {
+        recurrence.to_string()
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> Date {
+        s.parse().unwrap()
+    }
+
+    fn rule(s: &str) -> Recurrence {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn rules_round_trip_through_text() {
+        for s in [
+            "daily",
+            "weekly",
+            "weekly:mon,thu",
+            "monthly:15",
+            "after:3d",
+            "after:2w",
+        ] {
+            assert_eq!(rule(s).to_string(), s);
+        }
+
+        for s in [
+            "",
+            "hourly",
+            "weekly:",
+            "weekly:xyz",
+            "monthly:32",
+            "after:0d",
+            "after:3",
+            "after:3é",
+            "after:é",
+            "after:9999999999999999999w",
+        ] {
+            assert!(s.parse::<Recurrence>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn scheduled_rules_keep_their_cadence() {
+        // 2026-10-19 is a Monday
+        let due = Some(day("2026-10-19"));
+
+        assert_eq!(
+            rule("weekly").next_due(due, day("2026-10-18")),
+            Some(day("2026-10-26"))
+        );
+        assert_eq!(
+            rule("weekly:mon,thu").next_due(due, day("2026-10-19")),
+            Some(day("2026-10-22"))
+        );
+        assert_eq!(
+            rule("monthly").next_due(due, day("2026-10-19")),
+            Some(day("2026-11-19"))
+        );
+        assert_eq!(
+            rule("daily").next_due(due, day("2026-10-19")),
+            Some(day("2026-10-20"))
+        );
+    }
+
+    #[test]
+    fn late_completions_skip_the_days_that_passed() {
+        let due = Some(day("2026-10-19"));
+
+        assert_eq!(
+            rule("weekly").next_due(due, day("2026-11-04")),
+            Some(day("2026-11-09"))
+        );
+        assert_eq!(
+            rule("after:3d").next_due(due, day("2026-11-04")),
+            Some(day("2026-11-07"))
+        );
+    }
+
+    #[test]
+    fn rules_give_nothing_past_the_last_date() {
+        assert_eq!(
+            rule("after:99999999w").next_due(None, day("2026-10-19")),
+            None
+        );
+        assert_eq!(rule("daily").next_due(None, day("9999-12-31")), None);
+    }
+
+    #[test]
+    fn monthly_rules_clamp_to_short_months() {
+        let due = Some(day("2026-01-31"));
+
+        assert_eq!(
+            rule("monthly").next_due(due, day("2026-01-31")),
+            Some(day("2026-02-28"))
+        );
+        assert_eq!(
+            rule("monthly:31").first(day("2026-04-05")),
+            Some(day("2026-04-30"))
+        );
+    }
+
+    #[test]
+    fn first_occurrence_can_be_today() {
+        // A Saturday
+        let today = day("2026-10-17");
+
+        assert_eq!(rule("weekly:sat").first(today), Some(today));
+        assert_eq!(rule("weekly:mon").first(today), Some(day("2026-10-19")));
+        assert_eq!(rule("monthly:20").first(today), Some(day("2026-10-20")));
+    }
+}
@@ -0,0 +1,240 @@
+use serde_json::{Map, Value};
+
+/// Version of the on-disk format written by this build of marc
+pub const CURRENT_VERSION: u64 = 11;
+
+/// Files written before versioning was introduced have no version field
+const UNVERSIONED: u64 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `i + 1` to `i + 2`
+const MIGRATIONS: [Migration; (CURRENT_VERSION - 1) as usize] = [
+    v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9, v9_to_v10,
+    v10_to_v11,
+];
+
+/// Reads the schema version of a document
+pub fn version_of(doc: &Value) -> Result<u64, String> {
+    let object = doc
+        .as_object()
+        .ok_or("expected a JSON object at the top level")?;
+
+    match object.get("schema_version") {
+        None => Ok(UNVERSIONED),
+        Some(v) => v
+            .as_u64()
+            .filter(|v| *v >= UNVERSIONED)
+            .ok_or_else(|| format!("invalid schema_version {v}")),
+    }
+}
+
+/// Upgrades `doc` to `CURRENT_VERSION` one step at a time
+pub fn migrate(doc: &mut Value) -> Result<(), String> {
+    let mut version = version_of(doc)?;
+
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "it was written by a newer version of marc (schema version {version}, \
+             this marc supports up to {CURRENT_VERSION}); please upgrade marc"
+        ));
+    }
+
+    let object = doc
+        .as_object_mut()
+        .ok_or("expected a JSON object at the top level")?;
+
+    while version < CURRENT_VERSION {
+        let step = MIGRATIONS[(version - UNVERSIONED) as usize];
+        step(object).map_err(|e| {
+            format!(
+                "failed to migrate from schema version {version} to {}: {e}",
+                version + 1
+            )
+        })?;
+
+        version += 1;
+        object.insert("schema_version".to_string(), Value::from(version));
+    }
+
+    Ok(())
+}
+
+/// Introduces the `schema_version` field itself
+fn v1_to_v2(doc: &mut Map<String, Value>) -> Result<(), String> {
+    if !doc.get("items").is_some_and(Value::is_array) {
+        return Err("missing 'items' list".to_string());
+    }
+    Ok(())
+}
+
+/// Adds `created_at`, `updated_at` and `completed_at` to every item. When
+/// the items were created is unknown, which is recorded as 0 rather than
+/// the time of the migration so that migrating the same document twice
+/// gives the same result.
+fn v2_to_v3(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("created_at").or_insert(Value::from(0));
+        item.entry("updated_at").or_insert(Value::from(0));
+        item.entry("completed_at").or_insert(Value::Null);
+    })
+}
+
+/// Adds the optional `due` date to every item
+fn v3_to_v4(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("due").or_insert(Value::Null);
+    })
+}
+
+/// Adds the optional `priority` to every item
+fn v4_to_v5(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("priority").or_insert(Value::Null);
+    })
+}
+
+/// Replaces the single optional `tag` of every item by a `tags` list
+fn v5_to_v6(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        let tags = match item.remove("tag") {
+            Some(Value::String(tag)) => vec![Value::String(tag)],
+            _ => Vec::new(),
+        };
+        item.entry("tags").or_insert(Value::Array(tags));
+    })
+}
+
+/// Adds the list's declared `tags`
+fn v6_to_v7(doc: &mut Map<String, Value>) -> Result<(), String> {
+    doc.entry("tags").or_insert(Value::Object(Map::new()));
+    Ok(())
+}
+
+/// Adds the optional `parent` of subtasks to every item
+fn v7_to_v8(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("parent").or_insert(Value::Null);
+    })
+}
+
+/// Adds the `depends_on` list of blockers to every item
+fn v8_to_v9(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("depends_on").or_insert(Value::Array(Vec::new()));
+    })
+}
+
+/// Adds the recurrence rule and the series of recurring todos to every item
+fn v9_to_v10(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        item.entry("recur").or_insert(Value::Null);
+        item.entry("series").or_insert(Value::Null);
+    })
+}
+
+/// Replaces the `is_completed` flag of every item by its `status`
+fn v10_to_v11(doc: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_item(doc, |item| {
+        let status = match item.remove("is_completed") {
+            Some(Value::Bool(true)) => "done",
+            _ => "open",
+        };
+        item.entry("status")
+            .or_insert(Value::String(status.to_string()));
+    })
+}
+
+/// Runs `f` on every item of the document
+fn for_each_item(
+    doc: &mut Map<String, Value>,
+    mut f: impl FnMut(&mut Map<String, Value>),
+) -> Result<(), String> {
+    let items = doc
+        .get_mut("items")
+        .and_then(Value::as_array_mut)
+        .ok_or("missing 'items' list")?;
+
+    for item in items {
+        f(item.as_object_mut().ok_or("expected items to be objects")?);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unversioned_documents_are_version_one() {
+        let doc = json!({ "items": [] });
+        assert_eq!(version_of(&doc), Ok(1));
+    }
+
+    #[test]
+    fn migrate_brings_old_documents_to_current_version() {
+        let mut doc = json!({ "items": [] });
+
+        migrate(&mut doc).unwrap();
+
+        assert_eq!(version_of(&doc), Ok(CURRENT_VERSION));
+    }
+
+    #[test]
+    fn timestamps_of_old_items_are_unknown() {
+        let mut doc = json!({
+            "schema_version": 2,
+            "items": [{ "hash": "abc1234", "desc": "a", "is_completed": true, "tag": null }]
+        });
+
+        migrate(&mut doc).unwrap();
+
+        assert_eq!(doc["items"][0]["created_at"], json!(0));
+        assert_eq!(doc["items"][0]["completed_at"], json!(null));
+    }
+
+    #[test]
+    fn single_tags_become_tag_lists() {
+        let mut doc = json!({
+            "schema_version": 5,
+            "items": [
+                { "hash": "abc1234", "desc": "a", "is_completed": false, "tag": "work" },
+                { "hash": "def5678", "desc": "b", "is_completed": false, "tag": null }
+            ]
+        });
+
+        migrate(&mut doc).unwrap();
+
+        assert_eq!(doc["items"][0]["tags"], json!(["work"]));
+        assert_eq!(doc["items"][1]["tags"], json!([]));
+        assert!(doc["items"][0].get("tag").is_none());
+    }
+
+    #[test]
+    fn completion_flags_become_statuses() {
+        let mut doc = json!({
+            "schema_version": 10,
+            "items": [
+                { "hash": "abc1234", "desc": "a", "is_completed": true },
+                { "hash": "def5678", "desc": "b", "is_completed": false }
+            ]
+        });
+
+        migrate(&mut doc).unwrap();
+
+        assert_eq!(doc["items"][0]["status"], json!("done"));
+        assert_eq!(doc["items"][1]["status"], json!("open"));
+        assert!(doc["items"][0].get("is_completed").is_none());
+    }
+
+    #[test]
+    fn migrate_refuses_newer_documents() {
+        let mut doc = json!({ "schema_version": CURRENT_VERSION + 1, "items": [] });
+
+        let err = migrate(&mut doc).unwrap_err();
+
+        assert!(err.contains("newer version of marc"), "{err}");
+    }
+}
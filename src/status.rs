@@ -0,0 +1,195 @@
+//! The states a todo goes through, from open to done or cancelled.
+//!
+//! Besides the built-in states, `status.custom` names extra ones a todo can
+//! be in while it is still open, like `review`.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum Status {
+    #[default]
+    Open,
+    Started,
+    /// Waiting on someone or something outside the list
+    Waiting,
+    Done,
+    /// Won't be done
+    Cancelled,
+    /// A state named in `status.custom`
+    Custom(String),
+}
+
+/// The states every list knows about
+const BUILT_IN: [&str; 5] = ["open", "started", "waiting", "done", "cancelled"];
+
+impl Status {
+    /// Done or cancelled, with nothing left to do
+    pub fn is_closed(&self) -> bool {
+        matches!(self, Status::Done | Status::Cancelled)
+    }
+
+    /// Checks that a todo in this state can be moved to `to`. Closed todos
+    /// have to be reopened before anything else happens to them.
+    pub fn check_transition(&self, to: &Status) -> Result<(), String> {
+        if self == to {
+            return Err(format!("is already {self}"));
+        }
+
+        match to {
+            Status::Open => Ok(()),
+            _ if self.is_closed() => Err(format!("is {self}, reopen it first")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads a state given on the command line, which has to be built in or
+    /// one of the `custom` ones
+    pub fn parse(text: &str, custom: &[String]) -> Result<Status, String> {
+        let name = text.trim().to_lowercase();
+        let name = match name.as_str() {
+            "canceled" => "cancelled".to_string(),
+            _ => name,
+        };
+
+        if BUILT_IN.contains(&name.as_str()) || custom.contains(&name) {
+            return Ok(Status::from(name));
+        }
+
+        let mut known: Vec<&str> = BUILT_IN.to_vec();
+        known.extend(custom.iter().map(String::as_str));
+        Err(format!(
+            "unknown status '{text}', expected one of {}",
+            known.join(", ")
+        ))
+    }
+
+    /// Reads the custom states of `status.custom`, written as `review,delegated`
+    pub fn parse_custom(text: &str) -> Result<Vec<String>, String> {
+        let mut names: Vec<String> = Vec::new();
+
+        for name in text.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                return Err(format!(
+                    "invalid status '{name}', use lowercase letters, digits and '-'"
+                ));
+            }
+            if BUILT_IN.contains(&name) || name == "canceled" {
+                return Err(format!("'{name}' is a built-in status"));
+            }
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// The box `marc log` shows in front of todos in this state
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Status::Open => "[ ]",
+            Status::Started => "[>]",
+            Status::Waiting => "[~]",
+            Status::Done => "[x]",
+            Status::Cancelled => "[-]",
+            Status::Custom(_) => "[*]",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Open => "open",
+            Status::Started => "started",
+            Status::Waiting => "waiting",
+            Status::Done => "done",
+            Status::Cancelled => "cancelled",
+            Status::Custom(name) => name,
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl From<String> for Status {
+    fn from(name: String) -> Status {
+        match name.as_str() {
+            "open" => Status::Open,
+            "started" => Status::Started,
+            "waiting" => Status::Waiting,
+            "done" => Status::Done,
+            "cancelled" => Status::Cancelled,
+            _ => Status::Custom(name),
+        }
+    }
+}
+
+impl From<Status> for String {
+    fn from(status: Status) -> String {
+        status.to_string()
+    }
+}
+
+/// Reads the status of an item as written by this marc, or the
+/// `is_completed` flag of older ones, which history events recorded before
+/// the migration to statuses still carry
+pub(crate) fn deserialize_status<'de, D>(deserializer: D) -> Result<Status, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Completed(bool),
+        Status(Status),
+    }
+
+    Ok(match Raw::deserialize(deserializer)? {
+        Raw::Completed(true) => Status::Done,
+        Raw::Completed(false) => Status::Open,
+        Raw::Status(status) => status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_todos_have_to_be_reopened_first() {
+        assert!(Status::Open.check_transition(&Status::Started).is_ok());
+        assert!(Status::Waiting.check_transition(&Status::Done).is_ok());
+        assert!(Status::Done.check_transition(&Status::Open).is_ok());
+
+        assert_eq!(
+            Status::Done.check_transition(&Status::Cancelled),
+            Err("is done, reopen it first".to_string())
+        );
+        assert_eq!(
+            Status::Started.check_transition(&Status::Started),
+            Err("is already started".to_string())
+        );
+    }
+
+    #[test]
+    fn custom_states_have_to_be_configured() {
+        let custom = Status::parse_custom("review, delegated,review").unwrap();
+
+        assert_eq!(custom, ["review", "delegated"]);
+        assert_eq!(
+            Status::parse("Review", &custom),
+            Ok(Status::Custom("review".to_string()))
+        );
+        assert_eq!(Status::parse("canceled", &[]), Ok(Status::Cancelled));
+        assert!(Status::parse("review", &[]).is_err());
+
+        assert!(Status::parse_custom("done").is_err());
+        assert!(Status::parse_custom("In Review").is_err());
+    }
+}
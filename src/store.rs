@@ -0,0 +1,471 @@
+use crate::history::Event;
+use crate::journal::Journal;
+use crate::lock::FileLock;
+use crate::todo::{Filter, TodoItem, TodoList};
+use crate::{atomic, schema};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Where a `TodoList` is persisted.
+///
+/// `load` and `save` are all a backend has to provide; the per-item
+/// operations default to a full load/save round trip and can be overridden
+/// by backends that can do better.
+pub trait Store {
+    /// Reads the whole list, an empty one if nothing was saved yet
+    fn load(&self) -> Result<TodoList, Box<dyn Error>>;
+
+    /// Replaces the whole list
+    fn save(&self, list: &TodoList) -> Result<(), Box<dyn Error>>;
+
+    /// Human readable location of the data, used in messages
+    fn location(&self) -> String;
+
+    /// Reads the undo journal, an empty one if nothing was recorded yet
+    fn load_journal(&self) -> Result<Journal, Box<dyn Error>>;
+
+    /// Replaces the undo journal
+    fn save_journal(&self, journal: &Journal) -> Result<(), Box<dyn Error>>;
+
+    /// Every event recorded for the list, oldest first
+    fn load_history(&self) -> Result<Vec<Event>, Box<dyn Error>>;
+
+    /// Adds events to the end of the history
+    fn append_history(&self, events: &[Event]) -> Result<(), Box<dyn Error>>;
+
+    /// Whether any event was recorded yet
+    fn has_history(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(!self.load_history()?.is_empty())
+    }
+
+    /// Excludes other processes for a load/modify/save cycle.
+    ///
+    /// Backends that are not shared between processes need no lock.
+    fn lock(&self, _command: &str) -> Result<Option<FileLock>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Items matching `filter`, in list order
+    fn query(&self, filter: &Filter) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+        Ok(self.load()?.filtered(filter))
+    }
+
+    /// The part of the list showing `entries` needs: its declared tags, and
+    /// the subtasks and blockers of `entries`
+    fn context(&self, entries: &[TodoItem]) -> Result<TodoList, Box<dyn Error>> {
+        let mut list = self.load()?;
+        list.items.retain(|item| {
+            entries.iter().any(|entry| {
+                item.parent.as_ref() == Some(&entry.hash) || entry.depends_on.contains(&item.hash)
+            })
+        });
+        Ok(list)
+    }
+
+    /// Items whose hash starts with `prefix`
+    fn find(&self, prefix: &str) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+        Ok(self
+            .load()?
+            .items
+            .into_iter()
+            .filter(|item| item.hash.starts_with(prefix))
+            .collect())
+    }
+
+    /// Appends an item to the list
+    fn insert(&self, item: TodoItem) -> Result<(), Box<dyn Error>> {
+        let mut list = self.load()?;
+        list.items.push(item);
+        self.save(&list)
+    }
+
+    /// Replaces the item with the same hash. Returns false if there is none.
+    fn update(&self, item: &TodoItem) -> Result<bool, Box<dyn Error>> {
+        let mut list = self.load()?;
+
+        match list.items.iter_mut().find(|i| i.hash == item.hash) {
+            Some(existing) => {
+                *existing = item.clone();
+                self.save(&list)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Removes the item with exactly this hash
+    fn remove(&self, hash: &str) -> Result<Option<TodoItem>, Box<dyn Error>> {
+        let mut list = self.load()?;
+
+        match list.items.iter().position(|i| i.hash == hash) {
+            Some(index) => {
+                let removed = list.items.remove(index);
+                self.save(&list)?;
+                Ok(Some(removed))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// The list as a pretty-printed JSON document, `marc.json` by default
+pub struct JsonStore {
+    path: PathBuf,
+    lock_timeout: Duration,
+}
+
+impl JsonStore {
+    pub fn new(path: PathBuf, lock_timeout: Duration) -> Self {
+        JsonStore { path, lock_timeout }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the original file is kept before migrating it from `version`
+    fn backup_path(&self, version: u64) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".v{version}.bak"));
+        PathBuf::from(name)
+    }
+
+    /// The undo journal, next to the list
+    fn journal_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".journal");
+        PathBuf::from(name)
+    }
+
+    /// The event history, next to the list, one JSON event per line
+    fn history_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".history");
+        PathBuf::from(name)
+    }
+
+    /// Cleans up after a save that was interrupted before its rename.
+    ///
+    /// Leftover temp files may belong to a save still in progress in another
+    /// process, so they are only touched while holding the lock.
+    fn recover_interrupted_save(&self) -> Result<(), Box<dyn Error>> {
+        let path = &self.path;
+
+        if atomic::leftovers(path)?.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = if FileLock::is_held(path) {
+            None
+        } else {
+            match FileLock::try_acquire(path, "recover")? {
+                Some(guard) => Some(guard),
+                None => return Ok(()),
+            }
+        };
+
+        let restored = atomic::recover(path, |data| {
+            serde_json::from_str::<serde_json::Value>(data).is_ok()
+        })
+        .map_err(|e| format!("error: failed to recover todo file: {e}"))?;
+
+        if let Some(leftover) = restored {
+            eprintln!(
+                "warning: recovered todo file from interrupted save ({})",
+                leftover.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Store for JsonStore {
+    fn load(&self) -> Result<TodoList, Box<dyn Error>> {
+        let path = &self.path;
+
+        self.recover_interrupted_save()?;
+
+        if !path.exists() {
+            return Ok(TodoList::new());
+        }
+
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("error: failed to read todo file: {e}"))?;
+
+        if data.trim().is_empty() {
+            return Ok(TodoList::new());
+        }
+
+        let parse_error = |e: &dyn std::fmt::Display| {
+            format!(
+                "error: failed to parse todo file ({}). Error: {}",
+                path.display(),
+                e
+            )
+        };
+
+        let mut doc: serde_json::Value =
+            serde_json::from_str(&data).map_err(|e| parse_error(&e))?;
+        let version = schema::version_of(&doc).map_err(|e| parse_error(&e))?;
+
+        if version < schema::CURRENT_VERSION {
+            let backup = self.backup_path(version);
+            if !backup.exists() {
+                atomic::write(&backup, data.as_bytes()).map_err(|e| {
+                    format!(
+                        "error: failed to back up todo file before migrating ({}): {}",
+                        backup.display(),
+                        e
+                    )
+                })?;
+            }
+        }
+
+        schema::migrate(&mut doc)
+            .map_err(|e| format!("error: cannot load todo file ({}): {}", path.display(), e))?;
+
+        serde_json::from_value(doc).map_err(|e| parse_error(&e).into())
+    }
+
+    fn save(&self, list: &TodoList) -> Result<(), Box<dyn Error>> {
+        let path = &self.path;
+
+        let serialized = serde_json::to_string_pretty(list)
+            .map_err(|e| format!("error: failed to serialize todo data: {e}"))?;
+        atomic::write(path, serialized.as_bytes()).map_err(|e| {
+            format!(
+                "error: failed to write to todo file ({}): {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load_journal(&self) -> Result<Journal, Box<dyn Error>> {
+        let path = self.journal_path();
+
+        if !path.exists() {
+            return Ok(Journal::default());
+        }
+
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("error: failed to read undo journal: {e}"))?;
+
+        Journal::from_json(&data).map_err(|e| {
+            format!(
+                "error: failed to parse undo journal ({}): {}",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    fn save_journal(&self, journal: &Journal) -> Result<(), Box<dyn Error>> {
+        let path = self.journal_path();
+
+        let serialized = serde_json::to_string(journal)
+            .map_err(|e| format!("error: failed to serialize undo journal: {e}"))?;
+        atomic::write(&path, serialized.as_bytes()).map_err(|e| {
+            format!(
+                "error: failed to write undo journal ({}): {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(())
+    }
+
+    fn load_history(&self) -> Result<Vec<Event>, Box<dyn Error>> {
+        let path = self.history_path();
+
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let data =
+            fs::read_to_string(&path).map_err(|e| format!("error: failed to read history: {e}"))?;
+
+        // A line cut short by an interrupted append is skipped rather than
+        // making the whole history unreadable
+        let lines: Vec<&str> = data.lines().filter(|l| !l.trim().is_empty()).collect();
+        let mut events = Vec::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(event) => events.push(event),
+                Err(_) if i + 1 == lines.len() && !data.ends_with('\n') => {}
+                Err(e) => {
+                    return Err(format!(
+                        "error: failed to parse history ({}:{}): {}",
+                        path.display(),
+                        i + 1,
+                        e
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn append_history(&self, events: &[Event]) -> Result<(), Box<dyn Error>> {
+        let path = self.history_path();
+        let write_error = |e: &dyn std::fmt::Display| {
+            format!("error: failed to write history ({}): {}", path.display(), e)
+        };
+
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| write_error(&e))?;
+        file.write_all(lines.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| write_error(&e))?;
+        Ok(())
+    }
+
+    fn has_history(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(fs::metadata(self.history_path()).is_ok_and(|m| m.len() > 0))
+    }
+
+    fn lock(&self, command: &str) -> Result<Option<FileLock>, Box<dyn Error>> {
+        FileLock::acquire(&self.path, self.lock_timeout, command).map(Some)
+    }
+}
+
+/// Keeps the list in memory only, for tests and for embedding marc's logic
+/// in tools that persist the list themselves
+#[derive(Default)]
+pub struct MemoryStore {
+    list: RefCell<TodoList>,
+    journal: RefCell<Journal>,
+    history: RefCell<Vec<Event>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_list(list: TodoList) -> Self {
+        MemoryStore {
+            list: RefCell::new(list),
+            journal: RefCell::default(),
+            history: RefCell::default(),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn load(&self) -> Result<TodoList, Box<dyn Error>> {
+        Ok(self.list.borrow().clone())
+    }
+
+    fn save(&self, list: &TodoList) -> Result<(), Box<dyn Error>> {
+        *self.list.borrow_mut() = list.clone();
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        "<memory>".to_string()
+    }
+
+    fn load_journal(&self) -> Result<Journal, Box<dyn Error>> {
+        Ok(self.journal.borrow().clone())
+    }
+
+    fn save_journal(&self, journal: &Journal) -> Result<(), Box<dyn Error>> {
+        *self.journal.borrow_mut() = journal.clone();
+        Ok(())
+    }
+
+    fn load_history(&self) -> Result<Vec<Event>, Box<dyn Error>> {
+        Ok(self.history.borrow().clone())
+    }
+
+    fn append_history(&self, events: &[Event]) -> Result<(), Box<dyn Error>> {
+        self.history.borrow_mut().extend_from_slice(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo::sample_list;
+    use tempfile::TempDir;
+
+    #[test]
+    fn json_store_round_trips_a_list() {
+        let dir = TempDir::new().unwrap();
+        let store = JsonStore::new(dir.path().join("marc.json"), Duration::ZERO);
+        let list = sample_list();
+
+        store.save(&list).unwrap();
+
+        assert_eq!(store.load().unwrap(), list);
+    }
+
+    #[test]
+    fn missing_file_loads_as_empty_list() {
+        let dir = TempDir::new().unwrap();
+        let store = JsonStore::new(dir.path().join("marc.json"), Duration::ZERO);
+
+        assert!(store.load().unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn json_history_skips_a_torn_last_line() {
+        let dir = TempDir::new().unwrap();
+        let store = JsonStore::new(dir.path().join("marc.json"), Duration::ZERO);
+        let events = crate::history::diff(&TodoList::new(), &sample_list(), 0);
+
+        store.append_history(&events).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(store.history_path())
+            .unwrap();
+        file.write_all(b"{\"at\":1,\"ha").unwrap();
+
+        assert_eq!(store.load_history().unwrap(), events);
+    }
+
+    #[test]
+    fn item_operations_default_to_load_and_save() {
+        let store = MemoryStore::with_list(sample_list());
+        let hash = store.load().unwrap().items[0].hash.clone();
+
+        assert_eq!(store.find(&hash[..3]).unwrap().len(), 1);
+
+        let removed = store.remove(&hash).unwrap().unwrap();
+        assert_eq!(removed.desc, "write tests");
+        assert_eq!(store.load().unwrap().items.len(), 2);
+
+        store.insert(removed.clone()).unwrap();
+        assert_eq!(store.load().unwrap().items.last(), Some(&removed));
+    }
+}
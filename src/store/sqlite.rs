@@ -0,0 +1,540 @@
+use super::Store;
+use crate::history::Event;
+use crate::journal::Journal;
+use crate::lock::FileLock;
+use crate::schema;
+use crate::todo::{Filter, TodoItem, TodoList};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, OptionalExtension, Transaction, params, params_from_iter};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Items are kept whole as JSON in `data` so every field round-trips; the
+/// other columns and `item_tags` only exist to be indexed and queried.
+/// `is_completed` tells whether the item is closed, done or cancelled.
+const TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        hash         TEXT PRIMARY KEY,
+        position     INTEGER NOT NULL,
+        is_completed INTEGER NOT NULL,
+        data         TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS item_tags (
+        hash TEXT NOT NULL REFERENCES items(hash) ON DELETE CASCADE,
+        tag  TEXT NOT NULL,
+        PRIMARY KEY (hash, tag)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id   INTEGER PRIMARY KEY AUTOINCREMENT,
+        hash TEXT,
+        at   INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS items_position ON items(position);
+    CREATE INDEX IF NOT EXISTS items_is_completed ON items(is_completed);
+    CREATE INDEX IF NOT EXISTS item_tags_tag ON item_tags(tag);
+    CREATE INDEX IF NOT EXISTS history_hash ON history(hash);
+";
+
+/// The list in a SQLite database, `marc.db` by default
+pub struct SqliteStore {
+    path: PathBuf,
+    conn: Connection,
+    lock_timeout: Duration,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database, migrating it to the current schema
+    pub fn open(path: PathBuf, lock_timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(&path).map_err(|e| {
+            format!(
+                "error: failed to open todo database ({}): {}",
+                path.display(),
+                e
+            )
+        })?;
+        // Another marc holding a write on the database makes this one wait
+        // for it, as long as for the lock, rather than fail with "database
+        // is locked"
+        conn.busy_timeout(lock_timeout)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(TABLES)?;
+
+        let store = SqliteStore {
+            path,
+            conn,
+            lock_timeout,
+        };
+        store.upgrade()?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Brings a database written by an older marc to the current schema by
+    /// running its contents through the same migrations as the JSON file
+    fn upgrade(&self) -> Result<(), Box<dyn Error>> {
+        let version = match self.meta("schema_version")? {
+            Some(version) => version.parse::<u64>()?,
+            None => return Ok(()),
+        };
+
+        if version == schema::CURRENT_VERSION {
+            return Ok(());
+        }
+
+        let mut doc = self.document()?;
+        schema::migrate(&mut doc).map_err(|e| {
+            format!(
+                "error: cannot load todo database ({}): {}",
+                self.path.display(),
+                e
+            )
+        })?;
+
+        let mut backup = self.path.as_os_str().to_owned();
+        backup.push(format!(".v{version}.bak"));
+        let backup = PathBuf::from(backup);
+        if !backup.exists() {
+            fs::copy(&self.path, &backup).map_err(|e| {
+                format!(
+                    "error: failed to back up todo database before migrating ({}): {}",
+                    backup.display(),
+                    e
+                )
+            })?;
+        }
+
+        let list: TodoList = serde_json::from_value(doc)?;
+        self.save(&list)
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    /// The stored list as a JSON document, as it would appear in `marc.json`
+    fn document(&self) -> Result<Value, Box<dyn Error>> {
+        let mut doc = match self.meta("document")? {
+            Some(data) => serde_json::from_str(&data)?,
+            None => serde_json::json!({}),
+        };
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT data FROM items ORDER BY position")?;
+        let items = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<Vec<Value>, Box<dyn Error>>>()?;
+
+        doc["items"] = Value::Array(items);
+        Ok(doc)
+    }
+
+    /// Items selected by an SQL condition, in list order
+    fn select(
+        &self,
+        condition: &str,
+        values: Vec<SqlValue>,
+    ) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+        let sql = format!("SELECT data FROM items WHERE {condition} ORDER BY position");
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        stmt.query_map(params_from_iter(values), |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect()
+    }
+
+    fn write_item(tx: &Transaction, item: &TodoItem, position: i64) -> Result<(), Box<dyn Error>> {
+        tx.execute(
+            "INSERT OR REPLACE INTO items (hash, position, is_completed, data)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                item.hash,
+                position,
+                item.is_closed(),
+                serde_json::to_string(item)?
+            ],
+        )?;
+
+        tx.execute("DELETE FROM item_tags WHERE hash = ?1", [&item.hash])?;
+        for tag in &item.tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO item_tags (hash, tag) VALUES (?1, ?2)",
+                params![item.hash, tag],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Store for SqliteStore {
+    fn load(&self) -> Result<TodoList, Box<dyn Error>> {
+        if self.meta("schema_version")?.is_none() {
+            return Ok(TodoList::new());
+        }
+
+        Ok(serde_json::from_value(self.document()?)?)
+    }
+
+    fn save(&self, list: &TodoList) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute("DELETE FROM items", [])?;
+        for (position, item) in list.items.iter().enumerate() {
+            Self::write_item(&tx, item, position as i64)?;
+        }
+
+        let mut doc = serde_json::to_value(list)?;
+        if let Some(doc) = doc.as_object_mut() {
+            doc.remove("items");
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('document', ?1)",
+            [doc.to_string()],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', ?1)",
+            [list.schema_version.to_string()],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load_journal(&self) -> Result<Journal, Box<dyn Error>> {
+        match self.meta("journal")? {
+            Some(data) => Journal::from_json(&data).map_err(|e| {
+                format!(
+                    "error: failed to parse undo journal ({}): {}",
+                    self.path.display(),
+                    e
+                )
+                .into()
+            }),
+            None => Ok(Journal::default()),
+        }
+    }
+
+    fn save_journal(&self, journal: &Journal) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('journal', ?1)",
+            [serde_json::to_string(journal)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_history(&self) -> Result<Vec<Event>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT data FROM history ORDER BY id")?;
+
+        stmt.query_map([], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect()
+    }
+
+    fn append_history(&self, events: &[Event]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        for event in events {
+            tx.execute(
+                "INSERT INTO history (hash, at, data) VALUES (?1, ?2, ?3)",
+                params![event.hash, event.at as i64, serde_json::to_string(event)?],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn has_history(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT EXISTS (SELECT 1 FROM history)", [], |row| {
+                row.get(0)
+            })?)
+    }
+
+    fn lock(&self, command: &str) -> Result<Option<FileLock>, Box<dyn Error>> {
+        FileLock::acquire(&self.path, self.lock_timeout, command).map(Some)
+    }
+
+    fn query(&self, filter: &Filter) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+
+        if let Some(closed) = filter.closed {
+            conditions.push("is_completed = ?".to_string());
+            values.push(SqlValue::from(closed));
+        }
+
+        if !filter.statuses.is_empty() {
+            let marks = vec!["?"; filter.statuses.len()].join(", ");
+            conditions.push(format!("json_extract(data, '$.status') IN ({marks})"));
+            values.extend(
+                filter
+                    .statuses
+                    .iter()
+                    .map(|s| SqlValue::from(s.to_string())),
+            );
+        }
+
+        // A tag selects its subtags too, `work` matching `work/backend`
+        let mut within = |tag: &String| {
+            values.push(SqlValue::from(tag.clone()));
+            values.push(SqlValue::from(tag.chars().count() as i64 + 1));
+            values.push(SqlValue::from(format!("{tag}/")));
+            "tag = ? OR substr(tag, 1, ?) = ?"
+        };
+
+        if filter.any_tag && !filter.tags.is_empty() {
+            let matches: Vec<&str> = filter.tags.iter().map(&mut within).collect();
+            conditions.push(format!(
+                "hash IN (SELECT hash FROM item_tags WHERE {})",
+                matches.join(" OR ")
+            ));
+        } else {
+            for tag in &filter.tags {
+                conditions.push(format!(
+                    "hash IN (SELECT hash FROM item_tags WHERE {})",
+                    within(tag)
+                ));
+            }
+        }
+
+        let mut items = self.select(&conditions.join(" AND "), values)?;
+        items.retain(|item| filter.matches(item));
+        Ok(items)
+    }
+
+    fn context(&self, entries: &[TodoItem]) -> Result<TodoList, Box<dyn Error>> {
+        if self.meta("schema_version")?.is_none() {
+            return Ok(TodoList::new());
+        }
+
+        let mut doc = match self.meta("document")? {
+            Some(data) => serde_json::from_str(&data)?,
+            None => serde_json::json!({}),
+        };
+        doc["items"] = Value::Array(Vec::new());
+        let mut list: TodoList = serde_json::from_value(doc)?;
+
+        // The hashes go in as JSON arrays, however many there are
+        let hashes: Vec<&str> = entries.iter().map(|item| item.hash.as_str()).collect();
+        let blockers: Vec<&str> = entries
+            .iter()
+            .flat_map(|item| item.depends_on.iter().map(String::as_str))
+            .collect();
+        list.items = self.select(
+            "json_extract(data, '$.parent') IN (SELECT value FROM json_each(?))
+             OR hash IN (SELECT value FROM json_each(?))",
+            vec![
+                SqlValue::from(serde_json::to_string(&hashes)?),
+                SqlValue::from(serde_json::to_string(&blockers)?),
+            ],
+        )?;
+        Ok(list)
+    }
+
+    fn find(&self, prefix: &str) -> Result<Vec<TodoItem>, Box<dyn Error>> {
+        // Hashes are hex, and keeping GLOB metacharacters out lets the
+        // lookup use the primary key index
+        if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(Vec::new());
+        }
+
+        self.select("hash GLOB ?", vec![SqlValue::from(format!("{prefix}*"))])
+    }
+
+    fn insert(&self, item: TodoItem) -> Result<(), Box<dyn Error>> {
+        if self.meta("schema_version")?.is_none() {
+            let mut list = TodoList::new();
+            list.items.push(item);
+            return self.save(&list);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let position: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM items",
+            [],
+            |row| row.get(0),
+        )?;
+        Self::write_item(&tx, &item, position)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update(&self, item: &TodoItem) -> Result<bool, Box<dyn Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let position: Option<i64> = tx
+            .query_row(
+                "SELECT position FROM items WHERE hash = ?1",
+                [&item.hash],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match position {
+            Some(position) => {
+                Self::write_item(&tx, item, position)?;
+                tx.commit()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove(&self, hash: &str) -> Result<Option<TodoItem>, Box<dyn Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM items WHERE hash = ?1", [hash], |row| {
+                row.get(0)
+            })
+            .optional()?;
+
+        match data {
+            Some(data) => {
+                tx.execute("DELETE FROM items WHERE hash = ?1", [hash])?;
+                tx.commit()?;
+                Ok(Some(serde_json::from_str(&data)?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Status;
+    use crate::todo::sample_list;
+    use tempfile::TempDir;
+
+    fn open(dir: &TempDir) -> SqliteStore {
+        SqliteStore::open(dir.path().join("marc.db"), Duration::ZERO).unwrap()
+    }
+
+    #[test]
+    fn round_trips_a_list() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        let list = sample_list();
+
+        store.save(&list).unwrap();
+
+        assert_eq!(open(&dir).load().unwrap(), list);
+    }
+
+    #[test]
+    fn query_pushes_filters_down() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        store.save(&sample_list()).unwrap();
+
+        let filter = Filter {
+            tags: vec!["work".to_string()],
+            closed: Some(false),
+            ..Filter::default()
+        };
+        let items = store.query(&filter).unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].desc, "write tests");
+
+        let filter = Filter {
+            statuses: vec![Status::Done],
+            ..Filter::default()
+        };
+        assert_eq!(store.query(&filter).unwrap()[0].desc, "ship it");
+
+        let mut filter = Filter {
+            tags: vec!["work".to_string(), "home".to_string()],
+            ..Filter::default()
+        };
+        assert!(store.query(&filter).unwrap().is_empty());
+
+        filter.any_tag = true;
+        assert_eq!(store.query(&filter).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn query_includes_subtags() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        let mut list = TodoList::new();
+        list.add_item("db".to_string(), &["work/db".to_string()], 7);
+        list.add_item("shop".to_string(), &["workshop".to_string()], 7);
+        store.save(&list).unwrap();
+
+        let filter = Filter {
+            tags: vec!["work".to_string()],
+            ..Filter::default()
+        };
+        let items = store.query(&filter).unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].desc, "db");
+    }
+
+    #[test]
+    fn context_holds_subtasks_and_blockers() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        let mut list = sample_list();
+        list.items[1].parent = Some(list.items[0].hash.clone());
+        list.items[0].depends_on = vec![list.items[2].hash.clone()];
+        list.tags
+            .insert("work".to_string(), crate::todo::TagInfo::default());
+        store.save(&list).unwrap();
+
+        let context = store.context(&list.items[..1]).unwrap();
+
+        assert_eq!(context.items, list.items[1..]);
+        assert_eq!(context.tags, list.tags);
+        assert!(store.context(&list.items[1..2]).unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn item_operations_keep_list_order() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        let list = sample_list();
+        store.save(&list).unwrap();
+
+        let first = &list.items[0];
+        assert_eq!(store.find(&first.hash[..4]).unwrap(), vec![first.clone()]);
+        assert!(store.find("*").unwrap().is_empty());
+
+        let removed = store.remove(&first.hash).unwrap().unwrap();
+        store.insert(removed.clone()).unwrap();
+
+        let items = store.load().unwrap().items;
+        assert_eq!(items.len(), 3);
+        assert_eq!(items.last(), Some(&removed));
+    }
+}
@@ -0,0 +1,1268 @@
+use crate::date::{self, Date};
+use crate::recur::Recurrence;
+use crate::schema;
+use crate::status::{Status, deserialize_status};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Hashes are never shorter, so that shorter words are not taken for one
+pub const MIN_HASH_LENGTH: usize = 4;
+
+/// How important a todo is, `H`, `M` or `L`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    #[serde(rename = "L")]
+    Low,
+    #[serde(rename = "M")]
+    Medium,
+    #[serde(rename = "H")]
+    High,
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "h" | "high" => Ok(Priority::High),
+            "m" | "medium" => Ok(Priority::Medium),
+            "l" | "low" => Ok(Priority::Low),
+            _ => Err(format!("invalid priority '{s}', expected H, M or L")),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self {
+            Priority::High => "H",
+            Priority::Medium => "M",
+            Priority::Low => "L",
+        };
+        write!(f, "{letter}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TodoItem {
+    pub(crate) hash: String,
+    pub(crate) desc: String,
+    /// Read from the `is_completed` flag of items from before statuses
+    #[serde(alias = "is_completed", deserialize_with = "deserialize_status")]
+    pub(crate) status: Status,
+    /// Without duplicates, in the order they were given
+    #[serde(default, alias = "tag", deserialize_with = "deserialize_tags")]
+    pub(crate) tags: Vec<String>,
+    /// Seconds since the Unix epoch, 0 when unknown (items from before
+    /// timestamps were recorded)
+    #[serde(default)]
+    pub(crate) created_at: u64,
+    #[serde(default)]
+    pub(crate) updated_at: u64,
+    #[serde(default)]
+    pub(crate) completed_at: Option<u64>,
+    #[serde(default)]
+    pub(crate) due: Option<Date>,
+    #[serde(default)]
+    pub(crate) priority: Option<Priority>,
+    /// Hash of the item this one is a subtask of
+    #[serde(default)]
+    pub(crate) parent: Option<String>,
+    /// Hashes of the items that have to be done before this one
+    #[serde(default)]
+    pub(crate) depends_on: Vec<String>,
+    /// When set, completing the item adds its next instance
+    #[serde(default)]
+    pub(crate) recur: Option<Recurrence>,
+    /// Hash of the first item of the recurring series this one belongs to,
+    /// none for that first item
+    #[serde(default)]
+    pub(crate) series: Option<String>,
+}
+
+impl TodoItem {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn desc(&self) -> &str {
+        &self.desc
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.status == Status::Done
+    }
+
+    /// Done or cancelled
+    pub fn is_closed(&self) -> bool {
+        self.status.is_closed()
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Carries `tag` or one of its subtags, like `work/backend` for `work`
+    pub fn has_tag_within(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| is_within(t, tag))
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> u64 {
+        self.updated_at
+    }
+
+    pub fn completed_at(&self) -> Option<u64> {
+        self.completed_at
+    }
+
+    pub fn due(&self) -> Option<Date> {
+        self.due
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    pub fn recur(&self) -> Option<&Recurrence> {
+        self.recur.as_ref()
+    }
+
+    /// Hash of the first item of the series, the item's own for that one
+    pub fn series(&self) -> &str {
+        self.series.as_deref().unwrap_or(&self.hash)
+    }
+
+    pub fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    /// Not closed and due before `today`
+    pub fn is_overdue(&self, today: Date) -> bool {
+        !self.is_closed() && self.due.is_some_and(|due| due < today)
+    }
+
+    /// The open copy of a recurring item done at `now`, with a fresh hash
+    /// and the next due date of its rule, or none once the rule runs past
+    /// the last date
+    fn next_instance(&self, now: u64) -> Option<TodoItem> {
+        let recur = self.recur.as_ref()?;
+
+        Some(TodoItem {
+            hash: TodoList::generate_short_hash(&self.desc, &self.tags, self.hash.len()),
+            status: Status::Open,
+            created_at: now,
+            updated_at: now,
+            completed_at: None,
+            due: Some(recur.next_due(self.due, Date::from_timestamp(now))?),
+            depends_on: Vec::new(),
+            series: Some(self.series().to_string()),
+            ..self.clone()
+        })
+    }
+}
+
+/// Terminal colors a tag can be shown in
+const TAG_COLORS: [(&str, &str); 8] = [
+    ("black", "30"),
+    ("red", "31"),
+    ("green", "32"),
+    ("yellow", "33"),
+    ("blue", "34"),
+    ("magenta", "35"),
+    ("cyan", "36"),
+    ("white", "37"),
+];
+
+/// Tags are shown in cyan unless they say otherwise
+const DEFAULT_TAG_COLOR: &str = "36";
+
+/// What a list says about one of its tags
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TagInfo {
+    /// One of the names in `TAG_COLORS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl TagInfo {
+    /// Checks that `color` is a color tags can have
+    pub fn validate_color(color: &str) -> Result<(), String> {
+        if TAG_COLORS.iter().any(|(name, _)| *name == color) {
+            return Ok(());
+        }
+
+        let names: Vec<&str> = TAG_COLORS.iter().map(|(name, _)| *name).collect();
+        Err(format!(
+            "unknown color '{color}', expected one of {}",
+            names.join(", ")
+        ))
+    }
+
+    fn ansi_code(&self) -> &'static str {
+        self.color
+            .as_deref()
+            .and_then(|color| TAG_COLORS.iter().find(|(name, _)| *name == color))
+            .map_or(DEFAULT_TAG_COLOR, |(_, code)| code)
+    }
+}
+
+/// Declared tags by name
+pub type Tags = BTreeMap<String, TagInfo>;
+
+/// The ANSI color code a tag is shown in
+pub(crate) fn tag_color(tags: &Tags, tag: &str) -> &'static str {
+    tags.get(tag).map_or(DEFAULT_TAG_COLOR, TagInfo::ansi_code)
+}
+
+/// `#tag`, in the tag's color
+pub(crate) fn paint_tag(color: bool, tags: &Tags, tag: &str) -> String {
+    paint(color, tag_color(tags, tag), &format!("#{tag}"))
+}
+
+/// Reads the tags of an item as written by this marc, or the single `tag`
+/// of older ones, which history events recorded before the migration to
+/// tag sets still carry
+pub(crate) fn deserialize_tags<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        One(Option<String>),
+        Many(Vec<String>),
+    }
+
+    Ok(match Tags::deserialize(deserializer)? {
+        Tags::One(tag) => tag.into_iter().collect(),
+        Tags::Many(tags) => tags,
+    })
+}
+
+/// Takes the `+tag` and `#tag` words out of a description, returning what
+/// is left of it and the tags. Words like `#42` or `+3d` are not tags.
+pub fn extract_tags(desc: &str) -> (String, Vec<String>) {
+    let mut tags = Vec::new();
+    let mut words = Vec::new();
+
+    for word in desc.split_whitespace() {
+        match word.strip_prefix(['+', '#']) {
+            Some(tag) if is_tag_name(tag) => tags.push(tag.to_string()),
+            _ => words.push(word),
+        }
+    }
+
+    (words.join(" "), tags)
+}
+
+/// Checks that `tag` can be used as a tag name
+pub fn validate_tag(tag: &str) -> Result<(), String> {
+    if is_tag_name(tag) {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid tag '{tag}', tags are names separated by '/' that start with a letter \
+             and only contain letters, digits, '-' and '_'"
+        ))
+    }
+}
+
+/// Tags are `/`-separated names like `work/backend`, each starting with a
+/// letter and going on with letters, digits, `-` and `_`
+fn is_tag_name(tag: &str) -> bool {
+    tag.split('/').all(|name| {
+        name.starts_with(|c: char| c.is_alphabetic())
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+/// `tag` is `parent` or one of its subtags
+pub fn is_within(tag: &str, parent: &str) -> bool {
+    tag.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// `tag` and the tags above it, like `work/backend` and `work`
+pub fn ancestors(tag: &str) -> impl Iterator<Item = &str> {
+    tag.match_indices('/')
+        .map(|(i, _)| &tag[..i])
+        .chain(std::iter::once(tag))
+}
+
+/// Selects items by tag, status and due date, as `marc log` does
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    /// Items must carry all of these, or one of them with `any_tag`
+    pub tags: Vec<String>,
+    pub any_tag: bool,
+    /// Only closed items, or only the others
+    pub closed: Option<bool>,
+    /// Items must be in one of these states, when any are given
+    pub statuses: Vec<Status>,
+    /// Only items due before this day
+    pub due_before: Option<Date>,
+}
+
+impl Filter {
+    pub fn matches(&self, item: &TodoItem) -> bool {
+        if !self.tags.is_empty() {
+            let tagged = |tag: &String| item.has_tag_within(tag);
+            let matched = if self.any_tag {
+                self.tags.iter().any(tagged)
+            } else {
+                self.tags.iter().all(tagged)
+            };
+
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(day) = self.due_before
+            && item.due.is_none_or(|due| due >= day)
+        {
+            return false;
+        }
+
+        if !self.statuses.is_empty() && !self.statuses.contains(&item.status) {
+            return false;
+        }
+
+        match self.closed {
+            Some(closed) => item.is_closed() == closed,
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TodoList {
+    pub(crate) schema_version: u64,
+    pub(crate) items: Vec<TodoItem>,
+    /// Tags given a color or description with `marc tag --create`. Items
+    /// can carry tags that are not declared here.
+    #[serde(default)]
+    pub(crate) tags: Tags,
+}
+
+impl Default for TodoList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TodoList {
+    pub fn new() -> Self {
+        TodoList {
+            schema_version: schema::CURRENT_VERSION,
+            items: Vec::new(),
+            tags: Tags::new(),
+        }
+    }
+
+    pub fn items(&self) -> &[TodoItem] {
+        &self.items
+    }
+
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    /// Every declared or used tag and the tags above them, with how many
+    /// open and closed items carry the tag or one of its subtags
+    pub fn tag_counts(&self) -> BTreeMap<String, (usize, usize)> {
+        let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for tag in self.tags.keys() {
+            for ancestor in ancestors(tag) {
+                counts.entry(ancestor.to_string()).or_default();
+            }
+        }
+
+        for item in &self.items {
+            let mut within: Vec<&str> = item.tags.iter().flat_map(|t| ancestors(t)).collect();
+            within.sort_unstable();
+            within.dedup();
+
+            for tag in within {
+                let (open, done) = counts.entry(tag.to_string()).or_default();
+                if item.is_closed() {
+                    *done += 1;
+                } else {
+                    *open += 1;
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// Moves `old` and its subtags under `new` on every item carrying them,
+    /// so `work/db` becomes `job/db` when `work` goes to `job`, or strips
+    /// them when `new` is none. Items end up with each tag once. The
+    /// declarations move along unless the new tag has its own. Returns how
+    /// many items changed.
+    pub fn retag(&mut self, old: &str, new: Option<&str>, now: u64) -> usize {
+        let moved = |tag: &str| -> Option<String> {
+            let rest = &tag[old.len()..];
+            new.map(|new| format!("{new}{rest}"))
+        };
+        let mut changed = 0;
+
+        for item in &mut self.items {
+            if !item.has_tag_within(old) {
+                continue;
+            }
+
+            let mut tags: Vec<String> = Vec::new();
+            for tag in &item.tags {
+                let tag = if is_within(tag, old) {
+                    moved(tag)
+                } else {
+                    Some(tag.clone())
+                };
+                if let Some(tag) = tag
+                    && !tags.contains(&tag)
+                {
+                    tags.push(tag);
+                }
+            }
+
+            item.tags = tags;
+            item.updated_at = now;
+            changed += 1;
+        }
+
+        let declared: Vec<String> = self
+            .tags
+            .keys()
+            .filter(|tag| is_within(tag, old))
+            .cloned()
+            .collect();
+        for tag in declared {
+            let info = self.tags.remove(&tag).unwrap_or_default();
+            if let Some(new) = moved(&tag) {
+                self.tags.entry(new).or_insert(info);
+            }
+        }
+
+        changed
+    }
+
+    /// Appends a new item and returns it so callers can fill in the
+    /// optional fields. Repeated tags are kept once.
+    pub fn add_item(&mut self, desc: String, tags: &[String], hash_length: usize) -> &mut TodoItem {
+        let id = Self::generate_short_hash(&desc, tags, hash_length);
+        let now = date::now();
+        let mut unique_tags: Vec<String> = Vec::new();
+        for tag in tags {
+            if !unique_tags.contains(tag) {
+                unique_tags.push(tag.clone());
+            }
+        }
+        let new_item = TodoItem {
+            hash: id,
+            desc,
+            status: Status::Open,
+            tags: unique_tags,
+            created_at: now,
+            updated_at: now,
+            completed_at: None,
+            due: None,
+            priority: None,
+            parent: None,
+            depends_on: Vec::new(),
+            recur: None,
+            series: None,
+        };
+        self.items.push(new_item);
+        self.items.last_mut().unwrap()
+    }
+
+    /// Removes the item whose hash starts with `hash`, along with its
+    /// subtasks when `cascade` is set. Otherwise the subtasks move up to the
+    /// item's own parent.
+    pub fn rm_item(&mut self, hash: &str, cascade: bool) -> Option<TodoItem> {
+        let matching_items: Vec<usize> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.hash.starts_with(hash))
+            .map(|(i, _)| i)
+            .collect();
+
+        match matching_items.len() {
+            0 => None,
+            1 => {
+                let index = matching_items[0];
+                let removed = self.items[index].clone();
+                self.remove_items(std::slice::from_ref(&removed.hash), cascade);
+
+                Some(removed)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the items with these hashes, and their subtasks when
+    /// `cascade` is set. Subtasks that stay move up to the nearest parent
+    /// that does.
+    pub fn remove_items(&mut self, hashes: &[String], cascade: bool) {
+        let mut removed: Vec<String> = hashes.to_vec();
+        if cascade {
+            for hash in hashes {
+                removed.extend(self.descendants(hash));
+            }
+        }
+
+        let parents: Vec<(String, Option<String>)> = self
+            .items
+            .iter()
+            .map(|item| (item.hash.clone(), item.parent.clone()))
+            .collect();
+        let kept_parent = |mut parent: Option<String>| {
+            while let Some(hash) = parent.as_ref()
+                && removed.contains(hash)
+            {
+                parent = parents
+                    .iter()
+                    .find(|(h, _)| h == hash)
+                    .and_then(|(_, p)| p.clone());
+            }
+            parent
+        };
+
+        let now = date::now();
+        for item in &mut self.items {
+            if item.parent.as_ref().is_some_and(|p| removed.contains(p)) {
+                item.parent = kept_parent(item.parent.clone());
+                item.updated_at = now;
+            }
+        }
+        self.items.retain(|item| !removed.contains(&item.hash));
+        self.forget_dependencies(&removed, now);
+    }
+
+    /// Drops the dependencies on these hashes, for items that left the list
+    pub fn forget_dependencies(&mut self, hashes: &[String], now: u64) {
+        for item in &mut self.items {
+            if item.depends_on.iter().any(|h| hashes.contains(h)) {
+                item.depends_on.retain(|h| !hashes.contains(h));
+                item.updated_at = now;
+            }
+        }
+    }
+
+    /// The open items `item` waits for
+    pub fn open_blockers(&self, item: &TodoItem) -> Vec<&TodoItem> {
+        self.items
+            .iter()
+            .filter(|other| !other.is_closed() && item.depends_on.contains(&other.hash))
+            .collect()
+    }
+
+    /// Waits for an open item
+    pub fn is_blocked(&self, item: &TodoItem) -> bool {
+        !self.open_blockers(item).is_empty()
+    }
+
+    /// Makes the item at `index` wait for the item with hash `blocker`.
+    /// Refuses when `blocker` already waits for it, directly or not, as the
+    /// two could then never be done. Returns false if it already waited.
+    pub fn add_dependency(
+        &mut self,
+        index: usize,
+        blocker: &str,
+        now: u64,
+    ) -> Result<bool, String> {
+        let hash = self.items[index].hash.clone();
+
+        if hash == blocker || self.waits_for(blocker, &hash) {
+            return Err(format!(
+                "[{blocker}] already waits for [{hash}], which would make a cycle"
+            ));
+        }
+
+        let item = &mut self.items[index];
+        if item.depends_on.iter().any(|h| h == blocker) {
+            return Ok(false);
+        }
+        item.depends_on.push(blocker.to_string());
+        item.updated_at = now;
+        Ok(true)
+    }
+
+    /// Whether `from` waits for `to`, directly or through other items
+    fn waits_for(&self, from: &str, to: &str) -> bool {
+        let mut seen: Vec<&str> = Vec::new();
+        let mut pending = vec![from];
+
+        while let Some(hash) = pending.pop() {
+            if seen.contains(&hash) {
+                continue;
+            }
+            seen.push(hash);
+
+            if let Some(item) = self.items.iter().find(|item| item.hash == hash) {
+                if item.depends_on.iter().any(|h| h == to) {
+                    return true;
+                }
+                pending.extend(item.depends_on.iter().map(String::as_str));
+            }
+        }
+
+        false
+    }
+
+    /// Hashes of the subtasks of `hash`, their subtasks and so on
+    pub fn descendants(&self, hash: &str) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        let mut parents = vec![hash.to_string()];
+
+        while let Some(parent) = parents.pop() {
+            for item in &self.items {
+                if item.parent.as_ref() == Some(&parent) && !found.contains(&item.hash) {
+                    found.push(item.hash.clone());
+                    parents.push(item.hash.clone());
+                }
+            }
+        }
+
+        found
+    }
+
+    /// How many subtasks of `hash` are done, out of how many, none if it
+    /// has no subtasks
+    pub fn progress(&self, hash: &str) -> Option<(usize, usize)> {
+        let children: Vec<&TodoItem> = self
+            .items
+            .iter()
+            .filter(|item| item.parent.as_deref() == Some(hash))
+            .collect();
+
+        if children.is_empty() {
+            return None;
+        }
+        let done = children.iter().filter(|item| item.is_closed()).count();
+        Some((done, children.len()))
+    }
+
+    /// `entries` with each subtask right after its parent, and how deep it
+    /// is. Items whose parent is not among `entries` are shown at the top.
+    pub fn tree(entries: &[TodoItem]) -> Vec<(usize, &TodoItem)> {
+        let is_root = |item: &TodoItem| {
+            item.parent
+                .as_ref()
+                .is_none_or(|parent| !entries.iter().any(|e| e.hash == *parent))
+        };
+
+        let mut tree = Vec::new();
+        let mut stack: Vec<(usize, &TodoItem)> = entries
+            .iter()
+            .rev()
+            .filter(|item| is_root(item))
+            .map(|item| (0, item))
+            .collect();
+
+        while let Some((depth, item)) = stack.pop() {
+            // A list edited by hand could have a cycle
+            if tree
+                .iter()
+                .any(|(_, seen): &(usize, &TodoItem)| seen.hash == item.hash)
+            {
+                continue;
+            }
+            tree.push((depth, item));
+            stack.extend(
+                entries
+                    .iter()
+                    .rev()
+                    .filter(|child| child.parent.as_ref() == Some(&item.hash))
+                    .map(|child| (depth + 1, child)),
+            );
+        }
+
+        tree
+    }
+
+    /// Items matching `filter`, in list order
+    pub fn filtered(&self, filter: &Filter) -> Vec<TodoItem> {
+        self.items
+            .iter()
+            .filter(|item| filter.matches(item))
+            .cloned()
+            .collect()
+    }
+
+    /// Prints `entries` as a tree, using `list` for the tags' colors and the
+    /// progress of parents
+    pub fn list_items(entries: &[TodoItem], list: &TodoList, color: bool, now: u64) {
+        if entries.is_empty() {
+            println!("No entries");
+            return;
+        }
+
+        println!(
+            "{}",
+            paint(color, "1;31", &format!(" total {}", entries.len()))
+        );
+
+        for (depth, item) in Self::tree(entries) {
+            list.print_item(item, depth, color, now);
+        }
+    }
+
+    /// One line of `marc log`, indented by `depth` levels
+    pub fn print_item(&self, item: &TodoItem, depth: usize, color: bool, now: u64) {
+        let today = Date::from_timestamp(now);
+        let status = item.status.marker();
+        // Custom states all share a marker, so they are named too
+        let custom = match &item.status {
+            Status::Custom(name) => paint(color, "1", &format!("  {name}")),
+            _ => String::new(),
+        };
+
+        let priority = match item.priority {
+            Some(Priority::High) => paint(color, "1;35", "(H) "),
+            Some(priority) => format!("({priority}) "),
+            None => String::new(),
+        };
+
+        let due = match item.due {
+            Some(due) if !item.is_closed() => match today.days_until(due) {
+                ..0 => paint(color, "1;31", &format!("  overdue since {due}")),
+                0 => paint(color, "1;33", "  due today"),
+                1 => "  due tomorrow".to_string(),
+                _ => format!("  due {due}"),
+            },
+            Some(due) => format!("  due {due}"),
+            None => String::new(),
+        };
+
+        // Closed items show when they were closed, others how old they are
+        let age = match (item.completed_at, item.created_at) {
+            (Some(at), _) => format!("  {} {}", item.status, date::relative(at, now)),
+            (None, 0) => String::new(),
+            (None, at) => format!("  {}", date::relative(at, now)),
+        };
+
+        let blockers: Vec<&str> = self
+            .open_blockers(item)
+            .iter()
+            .map(|blocker| blocker.hash.as_str())
+            .collect();
+        let blocked = match blockers.as_slice() {
+            [] => String::new(),
+            hashes => paint(color, "33", &format!("  blocked by {}", hashes.join(", "))),
+        };
+
+        let recurs = match &item.recur {
+            Some(recur) => format!("  recurs {recur}"),
+            None => String::new(),
+        };
+
+        let progress = match self.progress(&item.hash) {
+            Some((done, total)) => format!(" [{done}/{total}]"),
+            None => String::new(),
+        };
+
+        let tags: Vec<String> = item
+            .tags
+            .iter()
+            .map(|tag| paint_tag(color, &self.tags, tag))
+            .collect();
+
+        println!(
+            "{} {}{} {} {}{}{}{}{}{}{}{}",
+            status,
+            "  ".repeat(depth),
+            item.hash,
+            tags.join(" "),
+            priority,
+            item.desc,
+            progress,
+            custom,
+            due,
+            recurs,
+            blocked,
+            paint(color, "2", &age),
+        );
+    }
+
+    /// Items of the recurring series `series`, in list order
+    pub fn series_items(&self, series: &str) -> Vec<&TodoItem> {
+        self.items
+            .iter()
+            .filter(|item| item.series() == series)
+            .collect()
+    }
+
+    fn generate_short_hash(desc: &str, tags: &[String], length: usize) -> String {
+        let mut hasher = DefaultHasher::new();
+        desc.hash(&mut hasher);
+        tags.hash(&mut hasher);
+        // Use current timestamp to ensure uniqueness
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .hash(&mut hasher);
+
+        let hash = hasher.finish();
+        format!("{hash:016x}")[..length.min(16)].to_string()
+    }
+
+    /// Completes the item whose hash starts with `hash`, see `set_status`
+    pub fn mark_done(&mut self, hash: &str, cascade: bool) -> Result<usize, TransitionError> {
+        self.set_status(hash, Status::Done, cascade)
+    }
+
+    /// Moves the item whose hash starts with `hash` to `status`, when its
+    /// current state allows it. Closing an item closes its open subtasks
+    /// too when `cascade` is set, otherwise they have to be closed first,
+    /// and an item can only be done once the items it waits for are.
+    /// Recurring items that are done get their next instance added at the
+    /// end of the list; cancelled ones end their series. Returns how many items changed.
+    pub fn set_status(
+        &mut self,
+        hash: &str,
+        status: Status,
+        cascade: bool,
+    ) -> Result<usize, TransitionError> {
+        let matching_items: Vec<usize> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.hash.starts_with(hash))
+            .map(|(i, _)| i)
+            .collect();
+
+        let index = match matching_items.as_slice() {
+            [] => {
+                return Err(TransitionError::NotFound(format!(
+                    "warning: no todo found with hash' {hash}'",
+                )));
+            }
+            [index] => *index,
+            _ => {
+                let matches: Vec<(String, String)> = matching_items
+                    .iter()
+                    .map(|&i| (self.items[i].hash.clone(), self.items[i].desc.clone()))
+                    .collect();
+                return Err(TransitionError::MultipleMatches(hash.to_string(), matches));
+            }
+        };
+
+        let item = &self.items[index];
+        if let Err(e) = item.status.check_transition(&status) {
+            return Err(TransitionError::Invalid(format!(
+                "warning: '{}' {e}",
+                item.desc
+            )));
+        }
+
+        // Only closing an item carries over to its subtasks
+        let open: Vec<usize> = if status.is_closed() {
+            let descendants = self.descendants(&item.hash);
+            self.items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| !item.is_closed() && descendants.contains(&item.hash))
+                .map(|(i, _)| i)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        if status == Status::Done {
+            // Blockers completed along do not count
+            let completing: Vec<&str> = open
+                .iter()
+                .chain([&index])
+                .map(|&i| self.items[i].hash.as_str())
+                .collect();
+            let mut blockers: Vec<(String, String)> = Vec::new();
+            for &i in open.iter().chain([&index]) {
+                for blocker in self.open_blockers(&self.items[i]) {
+                    let blocker = (blocker.hash.clone(), blocker.desc.clone());
+                    if !completing.contains(&blocker.0.as_str()) && !blockers.contains(&blocker) {
+                        blockers.push(blocker);
+                    }
+                }
+            }
+
+            if !blockers.is_empty() {
+                return Err(TransitionError::Blocked(
+                    self.items[index].desc.clone(),
+                    blockers,
+                ));
+            }
+        }
+
+        if !open.is_empty() && !cascade {
+            let verb = match status {
+                Status::Done => "complete",
+                _ => "cancel",
+            };
+            let subtasks = match open.len() {
+                1 => format!("an open subtask, {verb} it"),
+                n => format!("{n} open subtasks, {verb} them"),
+            };
+            return Err(TransitionError::OpenSubtasks(format!(
+                "warning: '{}' has {subtasks} first",
+                self.items[index].desc
+            )));
+        }
+
+        let now = date::now();
+        for &i in open.iter().chain([&index]) {
+            let item = &mut self.items[i];
+            item.status = status.clone();
+            item.completed_at = status.is_closed().then_some(now);
+            item.updated_at = now;
+
+            // Only done items come back; an item done again after being
+            // reopened already had its next instance added
+            let item = &self.items[i];
+            let has_next = self.items.iter().any(|other| {
+                other.hash != item.hash && other.series() == item.series() && !other.is_closed()
+            });
+            if status == Status::Done
+                && !has_next
+                && let Some(next) = item.next_instance(now)
+            {
+                self.items.push(next);
+            }
+        }
+        Ok(open.len() + 1)
+    }
+}
+
+/// Wraps `text` in an ANSI escape sequence when `color` is on
+pub(crate) fn paint(color: bool, code: &str, text: &str) -> String {
+    if color {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Why an item could not change state
+#[derive(Debug)]
+pub enum TransitionError {
+    NotFound(String),
+    /// The item's current state does not allow the change
+    Invalid(String),
+    OpenSubtasks(String),
+    Blocked(String, Vec<(String, String)>), // desc, vec of open blockers (id, desc)
+    MultipleMatches(String, Vec<(String, String)>), // prefix, vec of (id, desc)
+}
+
+/// Three todos for tests: "write tests" and "ship it", tagged work, and
+/// "buy milk" in between. "ship it" is done.
+#[cfg(test)]
+pub(crate) fn sample_list() -> TodoList {
+    let mut list = TodoList::new();
+    list.add_item("write tests".to_string(), &["work".to_string()], 7);
+    list.add_item("buy milk".to_string(), &[], 7);
+    list.add_item("ship it".to_string(), &["work".to_string()], 7);
+    list.items[2].status = Status::Done;
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn extracts_inline_tags() {
+        assert_eq!(
+            extract_tags("fix +work/backend login #urgent bug"),
+            (
+                "fix login bug".to_string(),
+                tags(&["work/backend", "urgent"])
+            )
+        );
+        assert_eq!(
+            extract_tags("close #42 in +3d and/or +a//b"),
+            ("close #42 in +3d and/or +a//b".to_string(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn retag_renames_merges_and_strips() {
+        let mut list = TodoList::new();
+        list.add_item("a".to_string(), &tags(&["old", "keep"]), 7);
+        list.add_item("b".to_string(), &tags(&["old", "new"]), 7);
+        list.tags.insert("old".to_string(), TagInfo::default());
+
+        assert_eq!(list.retag("old", Some("new"), 0), 2);
+        assert_eq!(list.items[0].tags, tags(&["new", "keep"]));
+        assert_eq!(list.items[1].tags, tags(&["new"]));
+        assert!(list.tags.contains_key("new"));
+
+        assert_eq!(list.retag("keep", None, 0), 1);
+        assert_eq!(list.items[0].tags, tags(&["new"]));
+    }
+
+    #[test]
+    fn retag_moves_subtrees() {
+        let mut list = TodoList::new();
+        list.add_item("a".to_string(), &tags(&["work/backend/db", "workshop"]), 7);
+        list.tags
+            .insert("work/backend".to_string(), TagInfo::default());
+
+        assert_eq!(list.retag("work", Some("job"), 0), 1);
+        assert_eq!(list.items[0].tags, tags(&["job/backend/db", "workshop"]));
+        assert!(list.tags.contains_key("job/backend"));
+    }
+
+    #[test]
+    fn parents_count_the_items_of_their_subtags() {
+        let mut list = TodoList::new();
+        list.add_item(
+            "a".to_string(),
+            &tags(&["work/backend", "work/frontend"]),
+            7,
+        );
+        list.add_item("b".to_string(), &tags(&["work"]), 7);
+
+        let counts = list.tag_counts();
+
+        assert_eq!(counts["work"], (2, 0));
+        assert_eq!(counts["work/backend"], (1, 0));
+        assert_eq!(counts.len(), 3);
+    }
+
+    /// `root` with subtasks `a` and `b`, and `a` with a subtask `a1`
+    fn nested_list() -> TodoList {
+        let mut list = TodoList::new();
+        for (desc, parent) in [
+            ("root", None),
+            ("a", Some(0)),
+            ("b", Some(0)),
+            ("a1", Some(1)),
+        ] {
+            let parent = parent.map(|i: usize| list.items[i].hash.clone());
+            list.add_item(desc.to_string(), &[], 7).parent = parent;
+        }
+        list
+    }
+
+    fn descs(list: &TodoList) -> Vec<&str> {
+        list.items.iter().map(|item| item.desc.as_str()).collect()
+    }
+
+    #[test]
+    fn tree_puts_subtasks_under_their_parent() {
+        let mut list = nested_list();
+        list.items.swap(1, 3);
+
+        let tree: Vec<(usize, &str)> = TodoList::tree(&list.items)
+            .into_iter()
+            .map(|(depth, item)| (depth, item.desc.as_str()))
+            .collect();
+
+        assert_eq!(tree, [(0, "root"), (1, "b"), (1, "a"), (2, "a1")]);
+    }
+
+    #[test]
+    fn completing_a_parent_blocks_or_cascades() {
+        let mut list = nested_list();
+        let root = list.items[0].hash.clone();
+
+        assert!(matches!(
+            list.mark_done(&root, false),
+            Err(TransitionError::OpenSubtasks(_))
+        ));
+        assert!(matches!(list.mark_done(&root, true), Ok(4)));
+        assert_eq!(list.progress(&root), Some((2, 2)));
+    }
+
+    #[test]
+    fn closed_items_have_to_be_reopened() {
+        let mut list = nested_list();
+        let (root, b) = (list.items[0].hash.clone(), list.items[2].hash.clone());
+
+        list.set_status(&b, Status::Started, false).unwrap();
+        assert!(matches!(
+            list.set_status(&root, Status::Cancelled, true),
+            Ok(4)
+        ));
+        assert!(
+            list.items
+                .iter()
+                .all(|item| item.status == Status::Cancelled)
+        );
+        assert!(list.items[0].completed_at.is_some());
+
+        assert!(matches!(
+            list.mark_done(&b, false),
+            Err(TransitionError::Invalid(_))
+        ));
+        list.set_status(&b, Status::Open, false).unwrap();
+        assert_eq!(list.items[2].completed_at, None);
+        assert!(list.mark_done(&b, false).is_ok());
+    }
+
+    #[test]
+    fn removing_a_parent_reparents_or_cascades() {
+        let mut list = nested_list();
+        let (root, a) = (list.items[0].hash.clone(), list.items[1].hash.clone());
+
+        let mut cascaded = list.clone();
+        cascaded.rm_item(&a, true);
+        assert_eq!(descs(&cascaded), ["root", "b"]);
+
+        list.rm_item(&a, false);
+        assert_eq!(descs(&list), ["root", "b", "a1"]);
+        assert_eq!(list.items[2].parent, Some(root));
+    }
+
+    #[test]
+    fn dependencies_cannot_make_cycles() {
+        let mut list = TodoList::new();
+        for desc in ["a", "b", "c"] {
+            list.add_item(desc.to_string(), &[], 7);
+        }
+        let hash = |i: usize| list.items[i].hash.clone();
+        let (a, b, c) = (hash(0), hash(1), hash(2));
+
+        assert_eq!(list.add_dependency(0, &b, 0), Ok(true));
+        assert_eq!(list.add_dependency(1, &c, 0), Ok(true));
+        assert_eq!(list.add_dependency(0, &b, 0), Ok(false));
+        assert!(list.add_dependency(2, &a, 0).is_err());
+        assert!(list.add_dependency(0, &a, 0).is_err());
+    }
+
+    #[test]
+    fn blocked_items_cannot_be_done() {
+        let mut list = TodoList::new();
+        list.add_item("ship".to_string(), &[], 7);
+        list.add_item("test".to_string(), &[], 7);
+        let (ship, test) = (list.items[0].hash.clone(), list.items[1].hash.clone());
+        list.add_dependency(0, &test, 0).unwrap();
+
+        match list.mark_done(&ship, false) {
+            Err(TransitionError::Blocked(desc, blockers)) => {
+                assert_eq!(desc, "ship");
+                assert_eq!(blockers, [(test.clone(), "test".to_string())]);
+            }
+            other => panic!("expected the todo to be blocked, got {other:?}"),
+        }
+
+        list.mark_done(&test, false).unwrap();
+        assert!(list.mark_done(&ship, false).is_ok());
+    }
+
+    #[test]
+    fn recurring_items_come_back_once_done() {
+        let mut list = TodoList::new();
+        let item = list.add_item("water plants".to_string(), &tags(&["home"]), 7);
+        item.due = "2100-01-01".parse().ok();
+        item.recur = "daily".parse().ok();
+        let first = item.hash.clone();
+
+        assert_eq!(list.mark_done(&first, false).unwrap(), 1);
+        let second = list.items[1].clone();
+        assert_ne!(second.hash, first);
+        assert!(!second.is_completed());
+        assert_eq!(second.due, "2100-01-02".parse().ok());
+        assert_eq!(second.tags, tags(&["home"]));
+        assert_eq!(second.series(), first);
+
+        list.items[1].recur = None;
+        list.mark_done(&second.hash, false).unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.series_items(&first).len(), 2);
+    }
+
+    #[test]
+    fn closing_a_reopened_recurring_item_adds_no_second_instance() {
+        let mut list = TodoList::new();
+        let item = list.add_item("water plants".to_string(), &[], 7);
+        item.recur = "daily".parse().ok();
+        let first = item.hash.clone();
+
+        list.mark_done(&first, false).unwrap();
+        list.set_status(&first, Status::Open, false).unwrap();
+        list.mark_done(&first, false).unwrap();
+
+        assert_eq!(list.items.len(), 2);
+
+        // Once the next instance is done too, the series goes on
+        let second = list.items[1].hash.clone();
+        list.mark_done(&second, false).unwrap();
+        assert_eq!(list.series_items(&first).len(), 3);
+    }
+
+    #[test]
+    fn cancelling_a_recurring_item_adds_no_instance() {
+        let mut list = TodoList::new();
+        let item = list.add_item("water plants".to_string(), &[], 7);
+        item.recur = "daily".parse().ok();
+        let hash = item.hash.clone();
+
+        list.set_status(&hash, Status::Cancelled, false).unwrap();
+
+        assert_eq!(list.items.len(), 1);
+    }
+
+    #[test]
+    fn tag_filters_match_all_or_any() {
+        let mut list = TodoList::new();
+        list.add_item("both".to_string(), &tags(&["backend", "urgent"]), 7);
+        list.add_item("one".to_string(), &tags(&["backend"]), 7);
+
+        let mut filter = Filter {
+            tags: tags(&["backend", "urgent"]),
+            ..Filter::default()
+        };
+        assert_eq!(list.filtered(&filter).len(), 1);
+
+        filter.any_tag = true;
+        assert_eq!(list.filtered(&filter).len(), 2);
+    }
+
+    #[test]
+    fn tag_filters_include_subtags() {
+        let mut list = TodoList::new();
+        list.add_item("db".to_string(), &tags(&["work/backend/db"]), 7);
+        list.add_item("shop".to_string(), &tags(&["workshop"]), 7);
+
+        let filter = Filter {
+            tags: tags(&["work"]),
+            ..Filter::default()
+        };
+
+        assert_eq!(list.filtered(&filter)[0].desc, "db");
+        assert_eq!(list.filtered(&filter).len(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+//! How pressing a todo is, used to sort `marc log` and to pick `marc next`.
+//!
+//! The score adds up the item's priority, how close its due date is, how
+//! old it is and per-tag bonuses, each scaled by a configurable weight.
+
+use crate::date::{Date, SECONDS_PER_DAY};
+use crate::todo::{Priority, TodoItem};
+
+/// Items due this many days ago or more get the full due weight
+const OVERDUE_DAYS: i64 = 7;
+/// Items due this many days ahead or more get the smallest share of it
+const DUE_HORIZON_DAYS: i64 = 14;
+/// Share of the due weight for items due far ahead
+const DUE_MINIMUM: f64 = 0.2;
+/// Items this old get the full age weight
+const AGE_DAYS: f64 = 365.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    pub priority: f64,
+    pub due: f64,
+    pub age: f64,
+    /// Added for items carrying the tag or one of its subtags
+    pub tags: Vec<(String, f64)>,
+}
+
+impl Weights {
+    /// Parses per-tag weights written as `work=2,someday=-3`
+    pub fn parse_tags(text: &str) -> Result<Vec<(String, f64)>, String> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (tag, weight) = pair
+                    .split_once('=')
+                    .ok_or(format!("expected 'tag=weight', found '{pair}'"))?;
+                let weight = weight
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|w| w.is_finite())
+                    .ok_or(format!("invalid weight for tag '{}'", tag.trim()))?;
+                Ok((tag.trim().to_string(), weight))
+            })
+            .collect()
+    }
+}
+
+/// The urgency of an item at `now`. Closed items are not urgent at all.
+pub fn urgency(item: &TodoItem, weights: &Weights, now: u64) -> f64 {
+    if item.is_closed() {
+        return 0.0;
+    }
+
+    let priority = match item.priority() {
+        Some(Priority::High) => 1.0,
+        Some(Priority::Medium) => 0.65,
+        Some(Priority::Low) => 0.3,
+        None => 0.0,
+    };
+
+    let due = match item.due() {
+        Some(due) => {
+            let days = Date::from_timestamp(now).days_until(due);
+            let span = (OVERDUE_DAYS + DUE_HORIZON_DAYS) as f64;
+            let progress = (days + OVERDUE_DAYS).clamp(0, OVERDUE_DAYS + DUE_HORIZON_DAYS);
+            1.0 - progress as f64 / span * (1.0 - DUE_MINIMUM)
+        }
+        None => 0.0,
+    };
+
+    let age = match item.created_at() {
+        0 => 0.0,
+        created_at => {
+            let days = now.saturating_sub(created_at) as f64 / SECONDS_PER_DAY as f64;
+            (days / AGE_DAYS).min(1.0)
+        }
+    };
+
+    let tags: f64 = weights
+        .tags
+        .iter()
+        .filter(|(tag, _)| item.has_tag_within(tag))
+        .map(|(_, weight)| weight)
+        .sum();
+
+    priority * weights.priority + due * weights.due + age * weights.age + tags
+}
+
+/// Sorts open items by decreasing urgency, keeping closed ones last and
+/// ties in list order
+pub fn sort(items: &mut [TodoItem], weights: &Weights, now: u64) {
+    items.sort_by(|a, b| {
+        a.is_closed()
+            .cmp(&b.is_closed())
+            .then_with(|| urgency(b, weights, now).total_cmp(&urgency(a, weights, now)))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo::TodoList;
+
+    const NOW: u64 = 1_792_195_200;
+
+    fn weights() -> Weights {
+        Weights {
+            priority: 6.0,
+            due: 12.0,
+            age: 2.0,
+            tags: vec![("work".to_string(), 3.0)],
+        }
+    }
+
+    #[test]
+    fn overdue_beats_high_priority() {
+        let mut list = TodoList::new();
+        let important = list.add_item("important".to_string(), &[], 7);
+        important.priority = Some(Priority::High);
+        important.created_at = NOW;
+        let late = list.add_item("late".to_string(), &[], 7);
+        late.due = "2026-10-01".parse().ok();
+        late.created_at = NOW;
+
+        let mut items = list.items().to_vec();
+        sort(&mut items, &weights(), NOW);
+
+        assert_eq!(items[0].desc(), "late");
+    }
+
+    #[test]
+    fn tag_weights_add_up() {
+        let mut list = TodoList::new();
+        let item = list.add_item("report".to_string(), &["work".to_string()], 7);
+        item.created_at = NOW;
+
+        assert_eq!(urgency(item, &weights(), NOW), 3.0);
+    }
+
+    #[test]
+    fn parses_tag_weights() {
+        assert_eq!(
+            Weights::parse_tags("work=2, someday=-3.5").unwrap(),
+            [("work".to_string(), 2.0), ("someday".to_string(), -3.5)]
+        );
+        assert!(Weights::parse_tags("work").is_err());
+        assert!(Weights::parse_tags("").unwrap().is_empty());
+    }
+}